@@ -0,0 +1,439 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::params::{self, Params};
+use crate::precision::Fixed;
+use crate::view::View;
+
+/// A keyframe as written in an animation file. Fields left out keep the value of the previous
+/// keyframe, or of the starting parameters for the first one.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keyframe {
+    /// Seconds from the start of the animation
+    pub time: f64,
+    pub center: Option<[Fixed; 2]>,
+    pub zoom: Option<f64>,
+    /// Counterclockwise, in radians like in parameter files
+    pub rotation: Option<f64>,
+    /// In palette lengths, not wrapped so that going from 0 to 3 cycles the palette three times
+    pub palette_offset: Option<f32>,
+    pub max_iterations: Option<u32>,
+}
+
+/// Layout of an animation file, a list of `[[keyframes]]` tables.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationFile {
+    keyframes: Vec<Keyframe>,
+}
+
+/// A keyframe with every value filled in.
+#[derive(Clone, Debug)]
+struct Key {
+    time: f64,
+    view: View,
+    palette_offset: f32,
+    max_iterations: u32,
+}
+
+/// Parameters changing over time, interpolated between keyframes. The zoom changes exponentially,
+/// so every second of the animation zooms in by the same factor, and the center moves so that
+/// the point both keyframes zoom towards stays in place.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    params: Params,
+    keys: Vec<Key>,
+}
+
+impl Animation {
+    /// Reads the keyframes of a TOML file, starting from `params`.
+    pub fn load(path: &Path, params: Params) -> Result<Animation, Error> {
+        let file: AnimationFile = toml::from_str(&fs::read_to_string(path)?)?;
+        Animation::new(params, &file.keyframes)
+    }
+
+    pub fn new(params: Params, keyframes: &[Keyframe]) -> Result<Animation, Error> {
+        if keyframes.is_empty() {
+            return Err(Error::Animation("there are no keyframes".to_owned()));
+        }
+
+        let mut previous = Key {
+            time: 0.0,
+            view: params.view.clone(),
+            palette_offset: params.fractal.palette_offset,
+            max_iterations: params.fractal.max_iterations,
+        };
+        let mut keys: Vec<Key> = Vec::with_capacity(keyframes.len());
+        for (i, keyframe) in keyframes.iter().enumerate() {
+            if i > 0 && keyframe.time <= previous.time {
+                return Err(Error::Animation(format!(
+                    "keyframe {} at {}s does not come after the one at {}s",
+                    i + 1,
+                    keyframe.time,
+                    previous.time,
+                )));
+            }
+            if keyframe.zoom.is_some_and(|zoom| !(zoom.is_finite() && zoom > 0.0)) {
+                return Err(Error::Animation(format!(
+                    "keyframe {} has a zoom that is not a finite number above 0",
+                    i + 1,
+                )));
+            }
+
+            let mut view = View {
+                center: keyframe.center.clone().unwrap_or(previous.view.center),
+                zoom: keyframe.zoom.unwrap_or(previous.view.zoom),
+                rotation: keyframe.rotation.unwrap_or(previous.view.rotation),
+            };
+            let fraction_limbs = View::fraction_limbs(view.zoom);
+            view.center = view.center.map(|c| c.with_fraction_limbs(fraction_limbs));
+
+            previous = Key {
+                time: keyframe.time,
+                view,
+                palette_offset: keyframe.palette_offset.unwrap_or(previous.palette_offset),
+                max_iterations: keyframe.max_iterations.unwrap_or(previous.max_iterations).max(1),
+            };
+            keys.push(previous.clone());
+        }
+
+        Ok(Animation { params, keys })
+    }
+
+    /// Seconds from the start to the last keyframe.
+    pub fn duration(&self) -> f64 {
+        self.keys.last().unwrap().time
+    }
+
+    /// Frames at `fps`, including one at the start and one at the last keyframe.
+    pub fn frame_count(&self, fps: f64) -> u32 {
+        (self.duration() * fps + 1e-9).floor() as u32 + 1
+    }
+
+    /// The parameters `time` seconds from the start, held before the first and after the last
+    /// keyframe.
+    pub fn params_at(&self, time: f64) -> Params {
+        let next = self.keys.iter().position(|key| key.time > time);
+        let key = match next {
+            Some(0) => self.keys[0].clone(),
+            Some(i) => {
+                let (from, to) = (&self.keys[i - 1], &self.keys[i]);
+                interpolate(from, to, (time - from.time) / (to.time - from.time))
+            }
+            None => self.keys.last().unwrap().clone(),
+        };
+
+        let mut params = self.params.clone();
+        params.view = key.view;
+        params.fractal.palette_offset = key.palette_offset.rem_euclid(1.0);
+        params.fractal.max_iterations = key.max_iterations;
+        params
+    }
+}
+
+/// The key a fraction `t` of the way from `from` to `to`.
+fn interpolate(from: &Key, to: &Key, t: f64) -> Key {
+    let lerp = |a: f64, b: f64| a + (b - a) * t;
+    let zoom = lerp(from.view.zoom.ln(), to.view.zoom.ln()).exp();
+
+    // Zooming about a fixed point p keeps (center - p) * zoom constant. With r = from.zoom /
+    // to.zoom the share of the way from `to` back to `from` is then (r^t - r) / (1 - r).
+    let ratio = from.view.zoom / to.view.zoom;
+    let remaining = if ratio.ln().abs() < 1e-9 {
+        1.0 - t
+    } else {
+        (ratio.powf(t) - ratio) / (1.0 - ratio)
+    };
+
+    // Measured from the deeper keyframe, the rounding error shrinks along with the pixels
+    let fraction_limbs = from.view.center[0].fraction_limbs().max(to.view.center[0].fraction_limbs());
+    let center = if ratio < 1.0 {
+        offset_center(&to.view.center, &from.view.center, remaining, fraction_limbs)
+    } else {
+        offset_center(&from.view.center, &to.view.center, 1.0 - remaining, fraction_limbs)
+    };
+
+    Key {
+        time: lerp(from.time, to.time),
+        view: View {
+            center: center.map(|c| c.with_fraction_limbs(View::fraction_limbs(zoom))),
+            zoom,
+            rotation: lerp(from.view.rotation, to.view.rotation),
+        },
+        palette_offset: lerp(from.palette_offset as f64, to.palette_offset as f64) as f32,
+        max_iterations: lerp(from.max_iterations as f64, to.max_iterations as f64).round() as u32,
+    }
+}
+
+/// `anchor + (other - anchor) * share`, in fixed point.
+fn offset_center(anchor: &[Fixed; 2], other: &[Fixed; 2], share: f64, fraction_limbs: usize) -> [Fixed; 2] {
+    let share = Fixed::from_f64(share, fraction_limbs);
+    [0, 1].map(|i| &anchor[i] + &(&(&other[i] - &anchor[i]) * &share))
+}
+
+/// The file of frame `frame`, with the `{frame}` placeholder of `template` replaced by the
+/// frame number padded to five digits. Templates without the placeholder are rejected, every
+/// frame would overwrite the one before.
+pub fn frame_path(template: &str, frame: u32) -> Result<PathBuf, Error> {
+    if !template.contains("{frame}") {
+        return Err(Error::Animation(format!(
+            "the output {} has no {{frame}} placeholder for the frame number",
+            template,
+        )));
+    }
+    Ok(PathBuf::from(template.replace("{frame}", &format!("{:05}", frame))))
+}
+
+/// Writes frames as an uncompressed YUV4MPEG2 stream, which video encoders such as ffmpeg read
+/// from a pipe. Colours are converted to 4:4:4 BT.601 YCbCr in the limited range encoders expect.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    size: [u32; 2],
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the stream header, every frame has to be of `size`.
+    pub fn new(mut writer: W, size: [u32; 2], fps: f64) -> Result<Y4mWriter<W>, Error> {
+        // The frame rate is a fraction, thousandths cover rates like 29.97
+        let fps_thousandths = (fps * 1000.0).round() as u64;
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:1000 Ip A1:1 C444",
+            size[0], size[1], fps_thousandths,
+        )?;
+
+        Ok(Y4mWriter {
+            writer,
+            size,
+            planes: Vec::with_capacity(3 * size[0] as usize * size[1] as usize),
+        })
+    }
+
+    pub fn write_frame(&mut self, image: &RgbaImage) -> Result<(), Error> {
+        let (width, height) = image.dimensions();
+        if [width, height] != self.size {
+            return Err(Error::Animation(format!(
+                "a {}x{} frame in a {}x{} stream",
+                width, height, self.size[0], self.size[1],
+            )));
+        }
+
+        let coefficients = [
+            (16.0, [65.481, 128.553, 24.966]),
+            (128.0, [-37.797, -74.203, 112.0]),
+            (128.0, [112.0, -93.786, -18.214]),
+        ];
+        self.planes.clear();
+        for (offset, [r, g, b]) in coefficients {
+            self.planes.extend(image.pixels().map(|pixel| {
+                let [red, green, blue, _] = pixel.0.map(|c| c as f32 / 255.0);
+                (offset + r * red + g * green + b * blue).round() as u8
+            }));
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&self.planes)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A short animation that ends where it starts, for GIFs and APNGs that repeat forever.
+#[derive(Clone, Debug)]
+pub struct Loop {
+    pub params: Params,
+    pub frames: u32,
+    /// Times the palette cycles through per loop, whole numbers keep the seam invisible
+    pub palette_cycles: i32,
+    /// Radius of the circle the Julia constant follows around `params.fractal.julia_c`, 0 keeps
+    /// it in place
+    pub julia_orbit: f64,
+}
+
+impl Loop {
+    /// The parameters of frame `frame` of `frames`, frame `frames` would repeat the first.
+    pub fn params_at(&self, frame: u32) -> Params {
+        let t = frame as f64 / self.frames.max(1) as f64;
+        let mut params = self.params.clone();
+        let fractal = &mut params.fractal;
+        fractal.palette_offset = (fractal.palette_offset + self.palette_cycles as f32 * t as f32).rem_euclid(1.0);
+
+        let (sin, cos) = (std::f64::consts::TAU * t).sin_cos();
+        fractal.julia_c = [
+            fractal.julia_c[0] + self.julia_orbit * cos,
+            fractal.julia_c[1] + self.julia_orbit * sin,
+        ];
+        params
+    }
+}
+
+/// Writes the frames of a `Loop` to an animated GIF or PNG as they are rendered. GIFs are
+/// quantized to 256 colours per frame, APNGs keep every colour and embed the parameters like
+/// `params::save_image`.
+pub enum LoopEncoder {
+    Gif { encoder: GifEncoder<BufWriter<File>>, delay: Delay },
+    Apng(png::Writer<BufWriter<File>>),
+}
+
+impl LoopEncoder {
+    /// Starts a `.gif` or `.png` file of `frames` frames of `params.resolution`, repeating forever.
+    pub fn create(path: &Path, params: &Params, frames: u32, fps: f64) -> Result<LoopEncoder, Error> {
+        let file = BufWriter::new(File::create(path)?);
+        if params::is_png(path) {
+            let mut encoder = params::png_encoder(file, params.resolution, params)?;
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_animated(frames, 0)?;
+            // Delays are fractions of a second with 16 bit parts
+            encoder.set_frame_delay((1000.0 / fps).round().clamp(1.0, u16::MAX as f64) as u16, 1000)?;
+            Ok(LoopEncoder::Apng(encoder.write_header()?))
+        } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gif")) {
+            // Speed 10 of 30 quantizes about as well as the slowest setting, much faster
+            let mut encoder = GifEncoder::new_with_speed(file, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            let delay = Delay::from_numer_denom_ms((1000.0 / fps).round() as u32, 1);
+            Ok(LoopEncoder::Gif { encoder, delay })
+        } else {
+            Err(Error::Animation(format!("loops are saved as .gif or .png, not {}", path.display())))
+        }
+    }
+
+    pub fn write_frame(&mut self, image: RgbaImage) -> Result<(), Error> {
+        match self {
+            LoopEncoder::Gif { encoder, delay } => encoder.encode_frame(Frame::from_parts(image, 0, 0, *delay))?,
+            LoopEncoder::Apng(writer) => writer.write_image_data(image.as_raw())?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        match self {
+            // The trailer is written when the encoder is dropped
+            LoopEncoder::Gif { encoder, .. } => drop(encoder),
+            LoopEncoder::Apng(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 1e-9 * expected.abs().max(1.0), "{} != {}", actual, expected);
+    }
+
+    /// From the default view to a point zoomed in 1000 times over two seconds.
+    fn animation() -> Animation {
+        let keyframes = [
+            Keyframe::default(),
+            Keyframe {
+                time: 2.0,
+                center: Some([Fixed::from_f64(-0.75, 2), Fixed::from_f64(0.1, 2)]),
+                zoom: Some(800.0),
+                rotation: Some(1.0),
+                palette_offset: Some(3.0),
+                max_iterations: Some(1000),
+            },
+        ];
+        Animation::new(Params::default(), &keyframes).unwrap()
+    }
+
+    #[test]
+    fn keyframes_are_reached_and_held() {
+        let animation = animation();
+        let start = Params::default();
+        for time in [-1.0, 0.0] {
+            let params = animation.params_at(time);
+            assert_eq!(params.view.zoom, start.view.zoom);
+            assert_eq!(params.view.center.clone().map(|c| c.to_f64()), [-0.5, 0.0]);
+            assert_eq!(params.fractal.max_iterations, start.fractal.max_iterations);
+        }
+        for time in [2.0, 5.0] {
+            let params = animation.params_at(time);
+            assert_eq!(params.view.zoom, 800.0);
+            assert_eq!(params.view.center.clone().map(|c| c.to_f64()), [-0.75, 0.1]);
+            assert_eq!(params.view.rotation, 1.0);
+            assert_eq!(params.fractal.palette_offset, 0.0);
+            assert_eq!(params.fractal.max_iterations, 1000);
+        }
+    }
+
+    #[test]
+    fn zoom_changes_exponentially_about_a_fixed_point() {
+        let params = animation().params_at(1.0);
+        assert_close(params.view.zoom, (0.8f64 * 800.0).sqrt());
+        assert_close(params.view.rotation, 0.5);
+        assert_eq!(params.fractal.max_iterations, 600);
+
+        // The point both keyframes keep at the same place on screen
+        let (from, to) = ([-0.5, 0.0], [-0.75, 0.1]);
+        for i in 0..2 {
+            let fixed_point = (from[i] * 0.8 - to[i] * 800.0) / (0.8 - 800.0);
+            let expected = fixed_point + (from[i] - fixed_point) * 0.8 / params.view.zoom;
+            assert_close(params.view.center[i].to_f64(), expected);
+        }
+    }
+
+    #[test]
+    fn frame_count_includes_both_ends() {
+        let animation = animation();
+        assert_eq!(animation.frame_count(30.0), 61);
+        assert_eq!(animation.frame_count(0.5), 2);
+        assert_eq!(animation.frame_count(0.1), 1);
+
+        let keyframes = [Keyframe { time: 1.0 / 3.0, ..Keyframe::default() }];
+        let third = Animation::new(Params::default(), &keyframes).unwrap();
+        assert_eq!(third.frame_count(3.0), 2);
+    }
+
+    #[test]
+    fn invalid_keyframes_are_rejected() {
+        assert!(Animation::new(Params::default(), &[]).is_err());
+        for zoom in [0.0, -2.0, f64::NAN, f64::INFINITY] {
+            let keyframes = [Keyframe { zoom: Some(zoom), ..Keyframe::default() }];
+            assert!(Animation::new(Params::default(), &keyframes).is_err(), "{}", zoom);
+        }
+        let keyframe = Keyframe { time: 1.0, ..Keyframe::default() };
+        assert!(Animation::new(Params::default(), &[keyframe.clone(), keyframe]).is_err());
+    }
+
+    #[test]
+    fn frame_paths_need_a_placeholder() {
+        assert_eq!(frame_path("frame-{frame}.png", 0).unwrap(), PathBuf::from("frame-00000.png"));
+        assert_eq!(frame_path("out/{frame}.tif", 1234).unwrap(), PathBuf::from("out/01234.tif"));
+        assert!(matches!(frame_path("out.png", 0), Err(Error::Animation(_))));
+        assert!(matches!(frame_path("{frames}.png", 0), Err(Error::Animation(_))));
+    }
+
+    #[test]
+    fn y4m_frames_follow_the_header() {
+        let mut output = Vec::new();
+        let mut writer = Y4mWriter::new(&mut output, [2, 1], 29.97).unwrap();
+        let image = RgbaImage::from_raw(2, 1, vec![255, 255, 255, 255, 0, 0, 0, 255]).unwrap();
+        writer.write_frame(&image).unwrap();
+        writer.write_frame(&image).unwrap();
+        assert!(matches!(writer.write_frame(&RgbaImage::new(1, 1)), Err(Error::Animation(_))));
+        writer.finish().unwrap();
+
+        let mut expected = b"YUV4MPEG2 W2 H1 F29970:1000 Ip A1:1 C444\n".to_vec();
+        for _ in 0..2 {
+            // White and black in the limited range, both without colour
+            expected.extend(b"FRAME\n");
+            expected.extend([235, 16, 128, 128, 128, 128]);
+        }
+        assert_eq!(output, expected);
+    }
+}
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+/// Pitch stays this far from straight up or down, where yaw loses its meaning
+const PITCH_LIMIT: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+/// Pinhole camera of the ray marched scenes. The world is y up, a yaw and pitch of 0 look
+/// along +z.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Camera {
+    pub position: [f32; 3],
+    /// Rotation around the y axis in radians, positive turns right
+    pub yaw: f32,
+    /// Rotation above the horizon in radians
+    pub pitch: f32,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            position: [0.0, 1.5, -6.0],
+            yaw: 0.0,
+            pitch: -0.15,
+            fov: 60.0,
+        }
+    }
+}
+
+impl Camera {
+    /// Forward, right and up unit vectors.
+    pub fn basis(&self) -> [[f32; 3]; 3] {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        let forward = [cos_pitch * sin_yaw, sin_pitch, cos_pitch * cos_yaw];
+        let right = [cos_yaw, 0.0, -sin_yaw];
+        let up = [
+            forward[1] * right[2] - forward[2] * right[1],
+            forward[2] * right[0] - forward[0] * right[2],
+            forward[0] * right[1] - forward[1] * right[0],
+        ];
+        [forward, right, up]
+    }
+
+    /// Turns by `yaw` and `pitch` radians, the pitch stops short of the poles.
+    pub fn look(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = (self.yaw + yaw) % std::f32::consts::TAU;
+        self.pitch = (self.pitch + pitch).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+    }
+
+    /// Moves `right` along the right vector, `up` along the world y axis and `forward` along the
+    /// view direction.
+    pub fn move_by(&mut self, right: f32, up: f32, forward: f32) {
+        let [forward_axis, right_axis, _] = self.basis();
+        for i in 0..3 {
+            self.position[i] += right * right_axis[i] + forward * forward_axis[i];
+        }
+        self.position[1] += up;
+    }
+}
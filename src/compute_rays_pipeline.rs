@@ -1,103 +1,337 @@
 use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
-use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferInheritanceInfo, CommandBufferInheritanceRenderPassType, CommandBufferUsage, RenderPassBeginInfo, SecondaryAutoCommandBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferInheritanceInfo, CommandBufferUsage, SecondaryAutoCommandBuffer};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
-use vulkano::device::Queue;
-use vulkano::image::StorageImage;
+use vulkano::device::{Device, Queue};
+use vulkano::image::{ImageAccess, StorageImage};
 use vulkano::image::view::ImageView;
-use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
-use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
-use vulkano::pipeline::{ComputePipeline, GraphicsPipeline, Pipeline, PipelineBindPoint};
-use vulkano::render_pass::{RenderPass, Subpass};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
+use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+use vulkano::shader::ShaderModule;
 
+use crate::camera::Camera;
+use crate::error::Error;
+use crate::fractal::FractalParams;
+use crate::mesh::MeshBuffers;
+use crate::pass::{Frame, FrameUniforms, Pass, PassStage, PathTrace, RayMarch, Region};
+use crate::perturbation::{self, ReferenceOrbit};
+use crate::sdf;
+use crate::view::View;
+use crate::vulkan;
+
+// The escape-time kernel is compiled once per precision, all variants share the same interface
 mod cs {
     vulkano_shaders::shader! {
         ty: "compute",
-        src: "
-            #version 460
+        path: "src/shaders/escape_time.comp",
+    }
+}
 
-            layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+mod cs_f64 {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/escape_time.comp",
+        define: [("PRECISION_DOUBLE", "1")],
+    }
+}
 
-            layout(set = 0, binding = 0, rgba8) uniform writeonly image2D img;
+mod cs_df64 {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/escape_time.comp",
+        define: [("PRECISION_DOUBLE_FLOAT", "1")],
+    }
+}
 
-            void main() {
-                vec2 norm_coordinates = (gl_GlobalInvocationID.xy + vec2(0.5)) / vec2(imageSize(img));
-                vec2 c = (norm_coordinates - vec2(0.5)) * 2.0 - vec2(1.0, 0.0);
+// Perturbation kernel with float or double mantissas, shares the interface of the escape-time kernel
+mod cs_perturbation {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/perturbation.comp",
+    }
+}
 
-                vec2 z = vec2(0.0, 0.0);
-                float i;
-                for (i = 0.0; i < 1.0; i += 0.005) {
-                    z = vec2(
-                        z.x * z.x - z.y * z.y + c.x,
-                        z.y * z.x + z.x * z.y + c.y
-                    );
+mod cs_perturbation_f64 {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/perturbation.comp",
+        define: [("PRECISION_DOUBLE", "1")],
+    }
+}
+
+// Sphere tracing kernel for signed distance scenes, with its own interface
+mod cs_ray_march {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/ray_march.comp",
+    }
+}
 
-                    if (length(z) > 4.0) {
-                        break;
-                    }
-                }
+// Path tracing kernel for signed distance scenes, shares the camera of the ray marching kernel
+mod cs_path_trace {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/path_trace.comp",
+    }
+}
 
-                vec4 to_write = vec4(vec3(i), 1.0);
-                imageStore(img, ivec2(gl_GlobalInvocationID.xy), to_write);
-            }
-        "
+// Converts the average of the path tracer for display
+mod cs_resolve {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/resolve.comp",
+    }
+}
+
+/// Work group size of the compute kernels, must match their `local_size_x` and `local_size_y`
+const LOCAL_SIZE: [u32; 2] = [8, 8];
+
+/// A precision is used while a pixel spans at least this many steps of it around the center
+const PRECISION_STEPS: f64 = 64.0;
+
+/// Arithmetic used by the compute kernels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    Single,
+    /// Native doubles, requires the `shader_float64` device feature
+    Double,
+    /// Doubles emulated with pairs of floats, slower but available everywhere
+    DoubleFloat,
+    /// Deltas to a fixed-point reference orbit, for zooms beyond any of the above
+    Perturbation,
+}
+
+impl Precision {
+    /// Least precise of `Single`, `deep` and `Perturbation` that resolves the pixels of `view` in
+    /// a region of `extent` pixels.
+    pub fn select(view: &View, extent: [u32; 2], deep: Precision) -> Precision {
+        let pixel_size = 2.0 / (view.zoom * extent[1] as f64);
+        let center = view.center.iter().map(|c| c.to_f64().abs()).fold(1.0, f64::max);
+        let resolves = |precision: Precision| {
+            pixel_size >= PRECISION_STEPS * center * 0.5f64.powi(precision.bits())
+        };
+
+        [Precision::Single, deep]
+            .into_iter()
+            .find(|precision| resolves(*precision))
+            .unwrap_or(Precision::Perturbation)
+    }
+
+    /// Significant bits of the center and pixel positions.
+    fn bits(self) -> i32 {
+        match self {
+            Precision::Single => 24,
+            Precision::Double => 53,
+            Precision::DoubleFloat => 48,
+            Precision::Perturbation => i32::MAX,
+        }
     }
 }
 
 pub struct ComputeRaysPipeline {
     gfx_queue: Arc<Queue>,
-    render_pass: Arc<RenderPass>,
     command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-    pipeline: Arc<ComputePipeline>
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    pipeline: Arc<ComputePipeline>,
+    /// Used once single precision can no longer resolve neighbouring pixels
+    deep_pipeline: Arc<ComputePipeline>,
+    deep_precision: Precision,
+    /// Used once the deep pipeline can no longer resolve neighbouring pixels
+    perturbation_pipeline: Arc<ComputePipeline>,
+    ray_march_pipeline: Arc<ComputePipeline>,
+    path_trace_pipeline: Arc<ComputePipeline>,
+    resolve_pipeline: Arc<ComputePipeline>,
 }
 
 impl ComputeRaysPipeline {
+    /// Deep zooms use native doubles if the device has `shader_float64` enabled and fall back to
+    /// double-float emulation otherwise. `memory_allocator` holds the reference orbits of the
+    /// perturbation kernel.
     pub fn new(
         gfx_queue: Arc<Queue>,
-        render_pass: Arc<RenderPass>,
         command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-    ) -> ComputeRaysPipeline {
+        memory_allocator: Arc<StandardMemoryAllocator>,
+    ) -> Result<ComputeRaysPipeline, Error> {
         let device = gfx_queue.device();
 
-        let cs = cs::load(device.clone())
-            .expect("Failed to create shader module.");
+        let pipeline = create_pipeline(device, cs::load(device.clone())?, "escape time float")?;
 
-        let pipeline = ComputePipeline::new(
-            device.clone(),
-            cs.entry_point("main").unwrap(),
-            &(),
-            None,
-            |_| {},
-        ).expect("Failed to create compute pipeline.");
+        let float64 = device.enabled_features().shader_float64;
+        let (deep_precision, deep_shader, perturbation_shader) = if float64 {
+            (Precision::Double, cs_f64::load(device.clone())?, cs_perturbation_f64::load(device.clone())?)
+        } else {
+            (Precision::DoubleFloat, cs_df64::load(device.clone())?, cs_perturbation::load(device.clone())?)
+        };
+        let deep_pipeline = create_pipeline(device, deep_shader, &format!("escape time {:?}", deep_precision))?;
+        let perturbation_pipeline = create_pipeline(device, perturbation_shader, "perturbation")?;
+        let ray_march_pipeline = create_pipeline(device, cs_ray_march::load(device.clone())?, "ray march")?;
+        let path_trace_pipeline = create_pipeline(device, cs_path_trace::load(device.clone())?, "path trace")?;
+        let resolve_pipeline = create_pipeline(device, cs_resolve::load(device.clone())?, "resolve")?;
+        tracing::info!("Deep zoom precision: {:?}", deep_precision);
 
-        ComputeRaysPipeline {
+        Ok(ComputeRaysPipeline {
             gfx_queue,
-            render_pass,
             command_buffer_allocator,
-            pipeline
-        }
+            memory_allocator,
+            pipeline,
+            deep_pipeline,
+            deep_precision,
+            perturbation_pipeline,
+            ray_march_pipeline,
+            path_trace_pipeline,
+            resolve_pipeline,
+        })
     }
 
-    fn create_descriptor_set(&self, image_view: Arc<ImageView<StorageImage>>) -> Arc<PersistentDescriptorSet>
-    {
+    /// Least precise arithmetic that resolves the pixels of `view` in a region of `extent` pixels.
+    pub fn precision(&self, view: &View, extent: [u32; 2]) -> Precision {
+        Precision::select(view, extent, self.deep_precision)
+    }
+
+    fn create_descriptor_set(
+        &self,
+        pipeline: &Arc<ComputePipeline>,
+        image_view: Arc<ImageView<StorageImage>>,
+        palette: Subbuffer<[[f32; 4]]>,
+        uniforms: Subbuffer<FrameUniforms>,
+        orbit: Option<Subbuffer<[[f32; 4]]>>,
+    ) -> Result<Arc<PersistentDescriptorSet>, Error> {
         let device = self.gfx_queue.device();
         let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone());
-        let pipeline_layout = self.pipeline.layout().set_layouts().get(0).unwrap();
+        let pipeline_layout = pipeline.layout().set_layouts().first().unwrap();
+
+        let mut writes = vec![
+            WriteDescriptorSet::image_view(0, image_view),
+            WriteDescriptorSet::buffer(1, palette),
+            WriteDescriptorSet::buffer(3, uniforms),
+        ];
+        if let Some(orbit) = orbit {
+            writes.push(WriteDescriptorSet::buffer(2, orbit));
+        }
 
-        let descriptor_set_layout_index = 0;
-        PersistentDescriptorSet::new(
+        Ok(PersistentDescriptorSet::new(
             &descriptor_set_allocator,
             pipeline_layout.clone(),
-            [WriteDescriptorSet::image_view(0, image_view.clone())],
-        ).unwrap()
-
+            writes,
+        )?)
     }
 
+    /// Renders `view` into the whole image, computing the reference orbit if the zoom needs one.
     pub fn draw(
         &self,
-        image_view: Arc<ImageView<StorageImage>>
-    ) -> SecondaryAutoCommandBuffer {
+        image_view: Arc<ImageView<StorageImage>>,
+        palette: Subbuffer<[[f32; 4]]>,
+        uniforms: Subbuffer<FrameUniforms>,
+        view: &View,
+        fractal: &FractalParams,
+    ) -> Result<SecondaryAutoCommandBuffer, Error> {
+        let region = Region {
+            view: view.clone(),
+            fractal: *fractal,
+            orbit: None,
+            offset: [0, 0],
+            extent: image_view.image().dimensions().width_height(),
+        };
+        self.draw_region(image_view, palette, uniforms, &region)
+    }
+
+    /// Renders `region` into its part of the image, leaving the rest untouched. The reference
+    /// orbit is computed here if the zoom needs one and the region has none. The palette shift
+    /// of `uniforms` is read when the commands run, not when they are recorded.
+    pub fn draw_region(
+        &self,
+        image_view: Arc<ImageView<StorageImage>>,
+        palette: Subbuffer<[[f32; 4]]>,
+        uniforms: Subbuffer<FrameUniforms>,
+        region: &Region,
+    ) -> Result<SecondaryAutoCommandBuffer, Error> {
+        let Region { view, fractal, offset, extent, .. } = region;
+        let mut builder = AutoCommandBufferBuilder::secondary(
+            self.command_buffer_allocator.as_ref(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferUsage::MultipleSubmit,
+            CommandBufferInheritanceInfo {
+                ..Default::default()
+            },
+        )?;
+
+        let precision = self.precision(view, *extent);
+        let (pipeline, orbit) = match precision {
+            Precision::Single => (&self.pipeline, None),
+            Precision::Double | Precision::DoubleFloat => (&self.deep_pipeline, None),
+            Precision::Perturbation => {
+                let orbit = match &region.orbit {
+                    Some(orbit) => orbit.clone(),
+                    None => ReferenceOrbit::new(view, fractal).upload(&self.memory_allocator)?,
+                };
+                (&self.perturbation_pipeline, Some(orbit))
+            }
+        };
+        let orbit_length = orbit.as_ref().map_or(0, |orbit| orbit.len() as u32);
+        let descriptor_set = self.create_descriptor_set(pipeline, image_view, palette, uniforms, orbit)?;
+
+        // The kernels add both halves of the center back together in their own precision
+        let center = [view.center[0].to_f64(), view.center[1].to_f64()];
+        let center_hi = [center[0] as f32, center[1] as f32];
+        let center_lo = [
+            (center[0] - center_hi[0] as f64) as f32,
+            (center[1] - center_hi[1] as f64) as f32,
+        ];
+        let (scale_mantissa, scale_exponent) = perturbation::split_exponent(1.0 / view.zoom);
+
+        let push_constants = cs::PushConstants {
+            center_hi,
+            center_lo,
+            zoom: view.zoom as f32,
+            rotation: view.rotation as f32,
+            max_iterations: fractal.max_iterations,
+            escape_radius: fractal.escape_radius,
+            palette_offset: fractal.palette_offset,
+            palette_period: fractal.palette_period,
+            julia_c: [fractal.julia_c[0] as f32, fractal.julia_c[1] as f32],
+            region_offset: [offset[0] as i32, offset[1] as i32],
+            region_size: [extent[0] as i32, extent[1] as i32],
+            kind: fractal.kind as u32,
+            scale_mantissa,
+            scale_exponent,
+            orbit_length,
+            interior_color: [
+                fractal.interior_color[0],
+                fractal.interior_color[1],
+                fractal.interior_color[2],
+                1.0,
+            ],
+        };
+
+        builder.bind_pipeline_compute(pipeline.clone())
+        .bind_descriptor_sets(
+            PipelineBindPoint::Compute,
+            pipeline.layout().clone(),
+            0,
+            descriptor_set
+        )
+        .push_constants(pipeline.layout().clone(), 0, push_constants)
+        .dispatch([
+            extent[0].div_ceil(LOCAL_SIZE[0]),
+            extent[1].div_ceil(LOCAL_SIZE[1]),
+            1,
+        ])?;
+
+        let commands = builder.build()?;
+        vulkan::set_object_name(&commands, &format!("compute {:?} at {:?}", precision, offset));
+        Ok(commands)
+    }
+
+    /// Ray marches `ray_march` into the `extent` sized part of the image at `offset`.
+    pub fn draw_ray_march(
+        &self,
+        image_view: Arc<ImageView<StorageImage>>,
+        ray_march: &RayMarch,
+        offset: [u32; 2],
+        extent: [u32; 2],
+    ) -> Result<SecondaryAutoCommandBuffer, Error> {
         let mut builder = AutoCommandBufferBuilder::secondary(
             self.command_buffer_allocator.as_ref(),
             self.gfx_queue.queue_family_index(),
@@ -105,20 +339,233 @@ impl ComputeRaysPipeline {
             CommandBufferInheritanceInfo {
                 ..Default::default()
             },
-        ).unwrap();
+        )?;
 
-        let descriptor_set = self.create_descriptor_set(image_view);
+        let camera_buffer = self.camera_buffer(&ray_march.camera)?;
 
-        builder.bind_pipeline_compute(self.pipeline.clone())
+        let pipeline = &self.ray_march_pipeline;
+        let descriptor_set = PersistentDescriptorSet::new(
+            &StandardDescriptorSetAllocator::new(self.gfx_queue.device().clone()),
+            pipeline.layout().set_layouts().first().unwrap().clone(),
+            [
+                WriteDescriptorSet::image_view(0, image_view),
+                WriteDescriptorSet::buffer(1, ray_march.objects.clone()),
+                WriteDescriptorSet::buffer(2, camera_buffer),
+            ]
+            .into_iter()
+            .chain(mesh_writes(&ray_march.meshes)),
+        )?;
+
+        let vec4 = |v: [f32; 3], s: f32| [v[0] * s, v[1] * s, v[2] * s, 0.0];
+        let scene = &ray_march.scene;
+        let light = scene.light_direction;
+        let light_length = light.iter().map(|c| c * c).sum::<f32>().sqrt().max(f32::MIN_POSITIVE);
+        let push_constants = cs_ray_march::PushConstants {
+            region_offset: [offset[0] as i32, offset[1] as i32],
+            region_size: [extent[0] as i32, extent[1] as i32],
+            light_direction: vec4(light, 1.0 / light_length),
+            sky_color: vec4(scene.sky_color, 1.0),
+            max_steps: scene.max_steps,
+            max_distance: scene.max_distance,
+            step_scale: scene.step_scale,
+            // `INSTRUCTION_SIZE` `vec4`s per instruction, the single entry of an empty scene is rounded away
+            instruction_count: (ray_march.objects.len() / sdf::INSTRUCTION_SIZE as u64) as u32,
+            shadows: scene.shadows as u32,
+            ambient_occlusion: scene.ambient_occlusion as u32,
+            triangle_count: ray_march.meshes.triangle_count,
+        };
+
+        builder.bind_pipeline_compute(pipeline.clone())
         .bind_descriptor_sets(
             PipelineBindPoint::Compute,
-            self.pipeline.layout().clone(),
+            pipeline.layout().clone(),
             0,
             descriptor_set
         )
-        .dispatch([1024 / 8, 1024 / 8, 1])
-        .unwrap();
+        .push_constants(pipeline.layout().clone(), 0, push_constants)
+        .dispatch([
+            extent[0].div_ceil(LOCAL_SIZE[0]),
+            extent[1].div_ceil(LOCAL_SIZE[1]),
+            1,
+        ])?;
+
+        let commands = builder.build()?;
+        vulkan::set_object_name(&commands, &format!("ray march at {:?}", offset));
+        Ok(commands)
+    }
+
+    /// Adds a sample of `path_trace` to the average in the `extent` sized part of
+    /// `accumulation_view` at `offset`, then resolves the average into the same part of the image.
+    /// The frame index of `uniforms` holds the samples averaged so far, 0 starts over.
+    pub fn draw_path_trace(
+        &self,
+        image_view: Arc<ImageView<StorageImage>>,
+        accumulation_view: Arc<ImageView<StorageImage>>,
+        uniforms: Subbuffer<FrameUniforms>,
+        path_trace: &PathTrace,
+        offset: [u32; 2],
+        extent: [u32; 2],
+    ) -> Result<SecondaryAutoCommandBuffer, Error> {
+        let mut builder = AutoCommandBufferBuilder::secondary(
+            self.command_buffer_allocator.as_ref(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferUsage::MultipleSubmit,
+            CommandBufferInheritanceInfo {
+                ..Default::default()
+            },
+        )?;
+
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(self.gfx_queue.device().clone());
+        let trace_pipeline = &self.path_trace_pipeline;
+        let trace_set = PersistentDescriptorSet::new(
+            &descriptor_set_allocator,
+            trace_pipeline.layout().set_layouts().first().unwrap().clone(),
+            [
+                WriteDescriptorSet::buffer(1, path_trace.objects.clone()),
+                WriteDescriptorSet::buffer(2, self.camera_buffer(&path_trace.camera)?),
+                WriteDescriptorSet::image_view(3, accumulation_view.clone()),
+                WriteDescriptorSet::buffer(4, uniforms),
+            ]
+            .into_iter()
+            .chain(mesh_writes(&path_trace.meshes)),
+        )?;
+        let resolve_pipeline = &self.resolve_pipeline;
+        let resolve_set = PersistentDescriptorSet::new(
+            &descriptor_set_allocator,
+            resolve_pipeline.layout().set_layouts().first().unwrap().clone(),
+            [
+                WriteDescriptorSet::image_view(0, image_view),
+                WriteDescriptorSet::image_view(1, accumulation_view),
+            ],
+        )?;
+
+        let scene = &path_trace.scene;
+        let settings = &path_trace.settings;
+        let region_offset = [offset[0] as i32, offset[1] as i32];
+        let region_size = [extent[0] as i32, extent[1] as i32];
+        let trace_constants = cs_path_trace::PushConstants {
+            region_offset,
+            region_size,
+            sky_color: [scene.sky_color[0], scene.sky_color[1], scene.sky_color[2], 0.0],
+            max_steps: scene.max_steps,
+            max_distance: scene.max_distance,
+            step_scale: scene.step_scale,
+            instruction_count: (path_trace.objects.len() / sdf::INSTRUCTION_SIZE as u64) as u32,
+            max_bounces: settings.max_bounces,
+            samples: settings.samples,
+            seed: settings.seed,
+            triangle_count: path_trace.meshes.triangle_count,
+        };
+        let resolve_constants = cs_resolve::PushConstants { region_offset, region_size };
+        let work_groups = [
+            extent[0].div_ceil(LOCAL_SIZE[0]),
+            extent[1].div_ceil(LOCAL_SIZE[1]),
+            1,
+        ];
+
+        // The builder inserts the barrier between writing the average and resolving it
+        builder.bind_pipeline_compute(trace_pipeline.clone())
+        .bind_descriptor_sets(
+            PipelineBindPoint::Compute,
+            trace_pipeline.layout().clone(),
+            0,
+            trace_set
+        )
+        .push_constants(trace_pipeline.layout().clone(), 0, trace_constants)
+        .dispatch(work_groups)?
+        .bind_pipeline_compute(resolve_pipeline.clone())
+        .bind_descriptor_sets(
+            PipelineBindPoint::Compute,
+            resolve_pipeline.layout().clone(),
+            0,
+            resolve_set
+        )
+        .push_constants(resolve_pipeline.layout().clone(), 0, resolve_constants)
+        .dispatch(work_groups)?;
 
-        builder.build().unwrap()
+        let commands = builder.build()?;
+        vulkan::set_object_name(&commands, &format!("path trace at {:?}", offset));
+        Ok(commands)
     }
+
+    /// Uniform buffer of the camera block both signed distance kernels declare.
+    fn camera_buffer(&self, camera: &Camera) -> Result<Subbuffer<cs_ray_march::Camera>, Error> {
+        // The ray through the top edge of the region is half the field of view above forward
+        let [forward, right, up] = camera.basis();
+        let scale = (0.5 * camera.fov.to_radians()).tan();
+        let vec4 = |v: [f32; 3], s: f32| [v[0] * s, v[1] * s, v[2] * s, 0.0];
+        Ok(Buffer::from_data(
+            &self.memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Upload,
+                ..Default::default()
+            },
+            cs_ray_march::Camera {
+                position: vec4(camera.position, 1.0),
+                forward: vec4(forward, 1.0),
+                right: vec4(right, scale),
+                up: vec4(up, scale),
+            },
+        )?)
+    }
+}
+
+impl Pass for ComputeRaysPipeline {
+    fn stage(&self) -> PassStage {
+        PassStage::Compute
+    }
+
+    fn record(&self, frame: &Frame) -> Result<Vec<SecondaryAutoCommandBuffer>, Error> {
+        frame.scene.regions
+            .iter()
+            .map(|region| self.draw_region(
+                frame.image_view.clone(),
+                frame.scene.palette.clone(),
+                frame.uniforms.clone(),
+                region,
+            ))
+            .chain(frame.scene.ray_march.iter().map(|ray_march| self.draw_ray_march(
+                frame.image_view.clone(),
+                ray_march,
+                [0, 0],
+                frame.image_view.image().dimensions().width_height(),
+            )))
+            .chain(frame.scene.path_trace.iter().map(|path_trace| self.draw_path_trace(
+                frame.image_view.clone(),
+                frame.accumulation_view.clone(),
+                frame.uniforms.clone(),
+                path_trace,
+                [0, 0],
+                frame.image_view.image().dimensions().width_height(),
+            )))
+            .collect()
+    }
+}
+
+/// Bindings of the mesh buffers in `mesh.glsl`, shared by both signed distance kernels.
+fn mesh_writes(meshes: &MeshBuffers) -> [WriteDescriptorSet; 5] {
+    [
+        WriteDescriptorSet::buffer(5, meshes.vertices.clone()),
+        WriteDescriptorSet::buffer(6, meshes.triangles.clone()),
+        WriteDescriptorSet::buffer(7, meshes.nodes.clone()),
+        WriteDescriptorSet::buffer(8, meshes.materials.clone()),
+        WriteDescriptorSet::buffer(9, meshes.texels.clone()),
+    ]
+}
+
+fn create_pipeline(device: &Arc<Device>, shader: Arc<ShaderModule>, name: &str) -> Result<Arc<ComputePipeline>, Error> {
+    let pipeline = ComputePipeline::new(
+        device.clone(),
+        shader.entry_point("main").ok_or_else(|| Error::NoEntryPoint(name.to_owned()))?,
+        &(),
+        None,
+        |_| {},
+    )?;
+    vulkan::set_object_name(&*pipeline, name);
+
+    Ok(pipeline)
 }
@@ -1,12 +1,20 @@
 use std::sync::Arc;
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
-use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferInheritanceInfo, CommandBufferInheritanceRenderPassType, CommandBufferUsage, RenderPassBeginInfo, SecondaryAutoCommandBuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferInheritanceInfo, CommandBufferUsage, SecondaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
 use vulkano::device::Queue;
+use vulkano::image::StorageImage;
+use vulkano::image::view::ImageView;
 use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
 use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
-use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineBindPoint};
 use vulkano::render_pass::{RenderPass, Subpass};
 
+use crate::error::Error;
+use crate::pass::{Frame, Pass, PassStage};
+use crate::vulkan;
+
 mod vs {
     vulkano_shaders::shader! {
         ty: "vertex",
@@ -34,11 +42,13 @@ mod fs {
 
             layout( location = 0 ) out vec4 f_color;
 
-            // layout(set = 0, binding = 0, rgba8) uniform readonly image2D inImg;
+            layout(set = 0, binding = 0, rgba8) uniform readonly image2D inImg;
 
             void main() {
-                f_color = vec4( 1.0f, 0.0f, 0.0f, 1.0f );
-                // f_color = imageLoad( inImg, ivec2(inUV) ).rgba;
+                // Scale the normalized coordinates to the image, the swapchain can have a different size
+                ivec2 size = imageSize( inImg );
+                ivec2 texel = min( ivec2( inUV * vec2( size ) ), size - 1 );
+                f_color = imageLoad( inImg, texel ).rgba;
             }
         ",
     }
@@ -56,37 +66,47 @@ impl DrawPipeline {
         gfx_queue: Arc<Queue>,
         render_pass: Arc<RenderPass>,
         command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
-    ) -> DrawPipeline {
+    ) -> Result<DrawPipeline, Error> {
         let device = gfx_queue.device();
 
-        let vs = vs::load(device.clone()).unwrap();
-        let fs = fs::load(device.clone()).unwrap();
+        let vs = vs::load(device.clone())?;
+        let fs = fs::load(device.clone())?;
 
         let pipeline = GraphicsPipeline::start()
-            .vertex_shader(vs.entry_point("main").unwrap(), ())
+            .vertex_shader(vs.entry_point("main").ok_or_else(|| Error::NoEntryPoint("draw vertex".to_owned()))?, ())
             .input_assembly_state(InputAssemblyState::new())
             .viewport_state(ViewportState::viewport_dynamic_scissor_irrelevant())
-            .fragment_shader(fs.entry_point("main").unwrap(), ())
+            .fragment_shader(fs.entry_point("main").ok_or_else(|| Error::NoEntryPoint("draw fragment".to_owned()))?, ())
             .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-            .build(device.clone())
-            .unwrap();
+            .build(device.clone())?;
+        vulkan::set_object_name(&*pipeline, "draw");
 
-        DrawPipeline {
+        Ok(DrawPipeline {
             gfx_queue,
             render_pass,
             command_buffer_allocator,
             pipeline
-        }
+        })
     }
 
-    fn create_descriptor_set() {
-
+    fn create_descriptor_set(&self, image_view: Arc<ImageView<StorageImage>>) -> Result<Arc<PersistentDescriptorSet>, Error>
+    {
+        let device = self.gfx_queue.device();
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone());
+        let pipeline_layout = self.pipeline.layout().set_layouts().first().unwrap();
+
+        Ok(PersistentDescriptorSet::new(
+            &descriptor_set_allocator,
+            pipeline_layout.clone(),
+            [WriteDescriptorSet::image_view(0, image_view)],
+        )?)
     }
 
     pub fn draw(
         &self,
-        viewport: &Viewport
-    ) -> SecondaryAutoCommandBuffer {
+        viewport: &Viewport,
+        image_view: Arc<ImageView<StorageImage>>
+    ) -> Result<SecondaryAutoCommandBuffer, Error> {
         let mut builder = AutoCommandBufferBuilder::secondary(
             self.command_buffer_allocator.as_ref(),
             self.gfx_queue.queue_family_index(),
@@ -95,12 +115,31 @@ impl DrawPipeline {
                 render_pass: Some(Subpass::from(self.render_pass.clone(), 0).unwrap().into()),
                 ..Default::default()
             },
-        ).unwrap();
+        )?;
+
+        let descriptor_set = self.create_descriptor_set(image_view)?;
 
         builder.set_viewport(0,[viewport.clone()]);
-        builder.bind_pipeline_graphics(self.pipeline.clone());
-        builder.draw(3, 1, 0, 0).unwrap();
+        builder.bind_pipeline_graphics(self.pipeline.clone())
+        .bind_descriptor_sets(
+            PipelineBindPoint::Graphics,
+            self.pipeline.layout().clone(),
+            0,
+            descriptor_set
+        );
+        builder.draw(3, 1, 0, 0)?;
+
+        let commands = builder.build()?;
+        vulkan::set_object_name(&commands, "draw");
+        Ok(commands)
+    }
+}
+impl Pass for DrawPipeline {
+    fn stage(&self) -> PassStage {
+        PassStage::Graphics
+    }
 
-        builder.build().unwrap()
+    fn record(&self, frame: &Frame) -> Result<Vec<SecondaryAutoCommandBuffer>, Error> {
+        Ok(vec![self.draw(frame.viewport, frame.image_view.clone())?])
     }
-}
\ No newline at end of file
+}
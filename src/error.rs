@@ -0,0 +1,276 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use vulkano::buffer::BufferError;
+use vulkano::command_buffer::{BuildError, ClearError, CommandBufferBeginError, CommandBufferExecError, CopyError, ExecuteCommandsError, PipelineExecutionError, RenderPassError};
+use vulkano::descriptor_set::DescriptorSetCreationError;
+use vulkano::device::DeviceCreationError;
+use vulkano::device::physical::PhysicalDeviceError;
+use vulkano::format::Format;
+use vulkano::image::ImageError;
+use vulkano::image::view::ImageViewCreationError;
+use vulkano::instance::InstanceCreationError;
+use vulkano::instance::debug::DebugUtilsMessengerCreationError;
+use vulkano::pipeline::compute::ComputePipelineCreationError;
+use vulkano::pipeline::graphics::GraphicsPipelineCreationError;
+use vulkano::render_pass::{FramebufferCreationError, RenderPassCreationError};
+use vulkano::shader::ShaderCreationError;
+use vulkano::swapchain::{AcquireError, SwapchainCreationError};
+use vulkano::sync::FlushError;
+use vulkano::{LoadingError, VulkanError};
+
+use crate::palette::PaletteError;
+
+/// Failure to set up Vulkan, render or present a frame.
+#[derive(Debug)]
+pub enum Error {
+    /// The Vulkan loader library is missing or broken
+    LibraryLoading(LoadingError),
+    InstanceCreation(InstanceCreationError),
+    DebugMessengerCreation(DebugUtilsMessengerCreationError),
+    /// No physical device meets the requirements, the message says which one is missing
+    NoDevice(String),
+    DeviceCreation(DeviceCreationError),
+    /// Enumerating devices or other calls without a more specific error
+    Vulkan(VulkanError),
+    SurfaceCreation(vulkano_win::CreationError),
+    /// A surface given to the renderer that was not created from a winit window
+    NotAWindow,
+    /// Querying the surface support, capabilities or formats of a device
+    SurfaceQuery(PhysicalDeviceError),
+    SwapchainCreation(SwapchainCreationError),
+    RenderPassCreation(RenderPassCreationError),
+    FramebufferCreation(FramebufferCreationError),
+    ImageCreation(ImageError),
+    ImageViewCreation(ImageViewCreationError),
+    BufferCreation(BufferError),
+    /// Mapping a host visible buffer to read or write it
+    BufferAccess(BufferError),
+    ShaderModule(ShaderCreationError),
+    /// A shader module without the entry point of the named pipeline
+    NoEntryPoint(String),
+    ComputePipelineCreation(ComputePipelineCreationError),
+    GraphicsPipelineCreation(GraphicsPipelineCreationError),
+    DescriptorSetCreation(DescriptorSetCreationError),
+    CommandBufferBegin(CommandBufferBeginError),
+    CommandBufferBuild(BuildError),
+    /// Recording a dispatch or draw
+    PipelineExecution(Box<PipelineExecutionError>),
+    /// Recording the beginning or end of a render pass
+    RenderPass(Box<RenderPassError>),
+    /// Recording secondary command buffers into a primary one
+    ExecuteCommands(Box<ExecuteCommandsError>),
+    /// Recording a copy between images and buffers
+    Copy(Box<CopyError>),
+    /// Recording the clear of an image
+    Clear(Box<ClearError>),
+    /// Scheduling a command buffer after the previous work of a frame
+    CommandBufferExec(CommandBufferExecError),
+    /// Acquiring the next swapchain image
+    Acquire(AcquireError),
+    /// Submitting or presenting a frame, or waiting for it to finish
+    Flush(FlushError),
+    /// Writing a rendered image to a file
+    Save(image::ImageError),
+    /// Reading or writing a file other than an image
+    Io(io::Error),
+    ParamsParse(toml::de::Error),
+    ParamsFormat(toml::ser::Error),
+    PngEncoding(png::EncodingError),
+    PngDecoding(png::DecodingError),
+    /// The PNG was not saved with its parameters
+    NoParams(PathBuf),
+    /// Parameters that cannot be rendered, the message says which
+    InvalidParams(String),
+    /// Pixels read back from the device that do not fill an image of this size
+    Readback([u32; 2]),
+    /// A screenshot of an image whose format cannot be converted to 8 bit RGBA
+    CaptureFormat(Format),
+    /// An unsupported poster format or size, the message says which
+    Poster(String),
+    /// Keyframes that cannot be animated, the message says why
+    Animation(String),
+    /// A signed distance scene the shader cannot evaluate, the message says why
+    Scene(String),
+    /// A mesh file that cannot be loaded, the message says why
+    Mesh(PathBuf, String),
+    /// A palette that is neither built in nor a readable palette file
+    Palette(PaletteError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::LibraryLoading(e) => write!(f, "could not load the Vulkan library, is a Vulkan driver installed? ({})", e),
+            Error::InstanceCreation(e) => write!(f, "failed to create the Vulkan instance: {}", e),
+            Error::DebugMessengerCreation(e) => write!(f, "failed to create the debug messenger: {}", e),
+            Error::NoDevice(message) => write!(f, "no suitable device: {}", message),
+            Error::DeviceCreation(e) => write!(f, "failed to create the device: {}", e),
+            Error::Vulkan(e) => write!(f, "Vulkan call failed: {}", e),
+            Error::SurfaceCreation(e) => {
+                // The vulkano-win message alone does not say what went wrong
+                write!(f, "{}", e)?;
+                if let Some(source) = error::Error::source(e) {
+                    write!(f, ": {}", source)?;
+                }
+                Ok(())
+            }
+            Error::NotAWindow => write!(f, "the surface was not created from a winit window"),
+            Error::SurfaceQuery(e) => write!(f, "failed to query the window surface: {}", e),
+            Error::SwapchainCreation(e) => write!(f, "failed to create the swapchain: {}", e),
+            Error::RenderPassCreation(e) => write!(f, "failed to create the render pass: {}", e),
+            Error::FramebufferCreation(e) => write!(f, "failed to create a framebuffer: {}", e),
+            Error::ImageCreation(e) => write!(f, "failed to create an image: {}", e),
+            Error::ImageViewCreation(e) => write!(f, "failed to create an image view: {}", e),
+            Error::BufferCreation(e) => write!(f, "failed to create a buffer: {}", e),
+            Error::BufferAccess(e) => write!(f, "failed to access a buffer: {}", e),
+            Error::ShaderModule(e) => write!(f, "failed to create a shader module: {}", e),
+            Error::NoEntryPoint(name) => write!(f, "the {} shader has no main entry point", name),
+            Error::ComputePipelineCreation(e) => write!(f, "failed to create the compute pipeline: {}", e),
+            Error::GraphicsPipelineCreation(e) => write!(f, "failed to create the graphics pipeline: {}", e),
+            Error::DescriptorSetCreation(e) => write!(f, "failed to create a descriptor set: {}", e),
+            Error::CommandBufferBegin(e) => write!(f, "failed to begin a command buffer: {}", e),
+            Error::CommandBufferBuild(e) => write!(f, "failed to build a command buffer: {}", e),
+            Error::PipelineExecution(e) => write!(f, "failed to record a dispatch or draw: {}", e),
+            Error::RenderPass(e) => write!(f, "failed to record the render pass: {}", e),
+            Error::ExecuteCommands(e) => write!(f, "failed to record secondary command buffers: {}", e),
+            Error::Copy(e) => write!(f, "failed to record a copy: {}", e),
+            Error::Clear(e) => write!(f, "failed to record a clear: {}", e),
+            Error::CommandBufferExec(e) => write!(f, "failed to execute a command buffer: {}", e),
+            Error::Acquire(e) => write!(f, "failed to acquire a swapchain image: {}", e),
+            Error::Flush(e) => write!(f, "failed to submit or present a frame: {}", e),
+            Error::Save(e) => write!(f, "failed to save the image: {}", e),
+            Error::Io(e) => write!(f, "failed to read or write a file: {}", e),
+            Error::ParamsParse(e) => write!(f, "invalid parameter file: {}", e),
+            Error::ParamsFormat(e) => write!(f, "failed to write the parameters: {}", e),
+            Error::PngEncoding(e) => write!(f, "failed to write the PNG: {}", e),
+            Error::PngDecoding(e) => write!(f, "failed to read the PNG: {}", e),
+            Error::NoParams(path) => write!(f, "{} has no embedded parameters", path.display()),
+            Error::InvalidParams(message) => write!(f, "invalid parameters: {}", message),
+            Error::Readback([width, height]) => write!(f, "the pixels read back do not fill a {}x{} image", width, height),
+            Error::CaptureFormat(format) => write!(f, "cannot take screenshots of {:?} images", format),
+            Error::Poster(message) => write!(f, "cannot export the poster: {}", message),
+            Error::Animation(message) => write!(f, "invalid animation: {}", message),
+            Error::Scene(message) => write!(f, "invalid scene: {}", message),
+            Error::Mesh(path, message) => write!(f, "failed to load the mesh {}: {}", path.display(), message),
+            Error::Palette(e) => write!(f, "failed to load the palette: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::LibraryLoading(e) => Some(e),
+            Error::InstanceCreation(e) => Some(e),
+            Error::DebugMessengerCreation(e) => Some(e),
+            Error::NoDevice(_) => None,
+            Error::DeviceCreation(e) => Some(e),
+            Error::Vulkan(e) => Some(e),
+            Error::SurfaceCreation(e) => Some(e),
+            Error::NotAWindow => None,
+            Error::SurfaceQuery(e) => Some(e),
+            Error::SwapchainCreation(e) => Some(e),
+            Error::RenderPassCreation(e) => Some(e),
+            Error::FramebufferCreation(e) => Some(e),
+            Error::ImageCreation(e) => Some(e),
+            Error::ImageViewCreation(e) => Some(e),
+            Error::BufferCreation(e) => Some(e),
+            Error::BufferAccess(e) => Some(e),
+            Error::ShaderModule(e) => Some(e),
+            Error::NoEntryPoint(_) => None,
+            Error::ComputePipelineCreation(e) => Some(e),
+            Error::GraphicsPipelineCreation(e) => Some(e),
+            Error::DescriptorSetCreation(e) => Some(e),
+            Error::CommandBufferBegin(e) => Some(e),
+            Error::CommandBufferBuild(e) => Some(e),
+            Error::PipelineExecution(e) => Some(e),
+            Error::RenderPass(e) => Some(e),
+            Error::ExecuteCommands(e) => Some(e),
+            Error::Copy(e) => Some(e),
+            Error::Clear(e) => Some(e),
+            Error::CommandBufferExec(e) => Some(e),
+            Error::Acquire(e) => Some(e),
+            Error::Flush(e) => Some(e),
+            Error::Save(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::ParamsParse(e) => Some(e),
+            Error::ParamsFormat(e) => Some(e),
+            Error::PngEncoding(e) => Some(e),
+            Error::PngDecoding(e) => Some(e),
+            Error::NoParams(_) => None,
+            Error::InvalidParams(_) => None,
+            Error::Readback(_) => None,
+            Error::CaptureFormat(_) => None,
+            Error::Poster(_) => None,
+            Error::Animation(_) => None,
+            Error::Scene(_) => None,
+            Error::Mesh(..) => None,
+            Error::Palette(e) => Some(e),
+        }
+    }
+}
+
+macro_rules! impl_from {
+    (boxed $($source:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$source> for Error {
+                fn from(e: $source) -> Self {
+                    Error::$variant(Box::new(e))
+                }
+            }
+        )*
+    };
+    ($($source:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$source> for Error {
+                fn from(e: $source) -> Self {
+                    Error::$variant(e)
+                }
+            }
+        )*
+    };
+}
+
+impl_from! {
+    LoadingError => LibraryLoading,
+    InstanceCreationError => InstanceCreation,
+    DebugUtilsMessengerCreationError => DebugMessengerCreation,
+    DeviceCreationError => DeviceCreation,
+    VulkanError => Vulkan,
+    vulkano_win::CreationError => SurfaceCreation,
+    PhysicalDeviceError => SurfaceQuery,
+    SwapchainCreationError => SwapchainCreation,
+    RenderPassCreationError => RenderPassCreation,
+    FramebufferCreationError => FramebufferCreation,
+    ImageError => ImageCreation,
+    ImageViewCreationError => ImageViewCreation,
+    BufferError => BufferCreation,
+    ShaderCreationError => ShaderModule,
+    ComputePipelineCreationError => ComputePipelineCreation,
+    GraphicsPipelineCreationError => GraphicsPipelineCreation,
+    DescriptorSetCreationError => DescriptorSetCreation,
+    CommandBufferBeginError => CommandBufferBegin,
+    BuildError => CommandBufferBuild,
+    CommandBufferExecError => CommandBufferExec,
+    AcquireError => Acquire,
+    FlushError => Flush,
+    image::ImageError => Save,
+    io::Error => Io,
+    toml::de::Error => ParamsParse,
+    toml::ser::Error => ParamsFormat,
+    png::EncodingError => PngEncoding,
+    png::DecodingError => PngDecoding,
+    PaletteError => Palette,
+}
+
+// The errors of recording commands are large, boxing them keeps every `Result` small
+impl_from! {
+    boxed
+    PipelineExecutionError => PipelineExecution,
+    RenderPassError => RenderPass,
+    ExecuteCommandsError => ExecuteCommands,
+    CopyError => Copy,
+    ClearError => Clear,
+}
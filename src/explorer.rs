@@ -0,0 +1,425 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use vulkano::buffer::Subbuffer;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use winit::event::{ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+use crate::camera::Camera;
+use crate::compute_rays_pipeline::Precision;
+use crate::error::Error;
+use crate::fractal::{FractalKind, FractalParams};
+use crate::mesh::{Mesh, MeshBuffers};
+use crate::palette::Palette;
+use crate::params::{self, Mode, Params};
+use crate::pass::{PathTrace, RayMarch, Region, Scene};
+use crate::perturbation::ReferenceOrbit;
+use crate::path_trace::PathSettings;
+use crate::sdf::SdfScene;
+use crate::view::View;
+
+/// Zoom factor of a single scroll wheel notch or key press
+const ZOOM_STEP: f64 = 1.2;
+/// Rotation in radians of a single key press
+const ROTATE_STEP: f64 = std::f64::consts::PI / 24.0;
+/// Palette shift of a single key press, in palette lengths
+const PALETTE_SHIFT_STEP: f32 = 1.0 / 32.0;
+/// Palette lengths per second while cycling
+const PALETTE_CYCLE_SPEED: f32 = 0.1;
+/// Size of the Julia preview relative to the window
+const PREVIEW_SCALE: u32 = 4;
+/// Distance in pixels between the Julia preview and the window border
+const PREVIEW_MARGIN: u32 = 16;
+/// Camera rotation in radians per pixel of mouse drag
+const LOOK_SPEED: f32 = 0.005;
+/// Camera movement of a single scroll wheel notch or key press
+const MOVE_STEP: f32 = 0.25;
+
+/// Interactive state of the windowed explorer: the view, fractal parameters and palettes, or the
+/// camera in a ray marched or path traced scene, driven by mouse and keyboard input.
+pub struct Explorer {
+    pub mode: Mode,
+    pub view: View,
+    pub fractal: FractalParams,
+    pub camera: Camera,
+    /// Set with `set_sdf`, which loads its meshes
+    sdf: SdfScene,
+    pub path: PathSettings,
+    /// View of the fractal kind that is not shown, restored when toggling back
+    other_view: View,
+    palettes: Vec<Palette>,
+    palette_index: usize,
+    palette_buffer: Subbuffer<[[f32; 4]]>,
+    /// The meshes of `sdf`, loaded once instead of for every frame
+    meshes: MeshBuffers,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    cursor_position: [f64; 2],
+    dragging: bool,
+    palette_cycling: bool,
+    /// Palette lengths cycled on top of the fractal's palette offset, applied through the
+    /// per-frame uniforms so cycling does not rebuild the command buffers
+    palette_shift: f32,
+    /// Shows the Julia set of the point under the cursor while exploring the Mandelbrot set
+    preview: bool,
+}
+
+impl Explorer {
+    /// Starts with `palette`, the other built-in palettes can be cycled through afterwards.
+    pub fn new(
+        fractal: FractalParams,
+        palette: Palette,
+        memory_allocator: Arc<StandardMemoryAllocator>,
+    ) -> Result<Explorer, Error> {
+        let mut palettes: Vec<Palette> = Palette::BUILT_IN
+            .iter()
+            .filter(|name| **name != palette.name)
+            .filter_map(|name| Palette::built_in(name))
+            .collect();
+        palettes.insert(0, palette);
+        let palette_buffer = palettes[0].upload(&memory_allocator)?;
+        let sdf = SdfScene::default();
+        let meshes = Mesh::default().upload(&memory_allocator)?;
+
+        let other_kind = match fractal.kind {
+            FractalKind::Mandelbrot => FractalKind::Julia,
+            FractalKind::Julia => FractalKind::Mandelbrot,
+        };
+
+        Ok(Explorer {
+            mode: Mode::Fractal,
+            view: fractal.kind.default_view(),
+            fractal,
+            camera: Camera::default(),
+            sdf,
+            path: PathSettings::default(),
+            other_view: other_kind.default_view(),
+            palettes,
+            palette_index: 0,
+            palette_buffer,
+            meshes,
+            memory_allocator,
+            cursor_position: [0.0, 0.0],
+            dragging: false,
+            palette_cycling: false,
+            palette_shift: 0.0,
+            preview: false,
+        })
+    }
+
+    /// Applies mouse and keyboard input, returns whether the rendered parameters changed.
+    pub fn handle_event(&mut self, event: &WindowEvent, window_size: [f64; 2]) -> bool {
+        match *event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let new_position = [position.x, position.y];
+                let delta = [new_position[0] - self.cursor_position[0], new_position[1] - self.cursor_position[1]];
+                self.cursor_position = new_position;
+
+                if self.dragging {
+                    match self.mode {
+                        Mode::Fractal => self.view.pan(delta, window_size),
+                        // Dragging moves the scene along with the cursor, like panning
+                        Mode::Sdf | Mode::Path => {
+                            self.camera.look(-delta[0] as f32 * LOOK_SPEED, delta[1] as f32 * LOOK_SPEED)
+                        }
+                    }
+                }
+                self.dragging || self.showing_preview()
+            }
+            WindowEvent::MouseInput { state, button: MouseButton::Left, .. } => {
+                self.dragging = state == ElementState::Pressed;
+                false
+            }
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Right, .. } => {
+                if self.mode != Mode::Fractal || self.fractal.kind != FractalKind::Mandelbrot {
+                    return false;
+                }
+                self.fractal.julia_c = self.view.pixel_to_plane(self.cursor_position, window_size);
+                self.toggle_kind();
+                tracing::info!("Julia set of {:?}", self.fractal.julia_c);
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y as f64,
+                    MouseScrollDelta::PixelDelta(position) => position.y / 50.0,
+                };
+                match self.mode {
+                    Mode::Fractal => self.view.zoom_at(self.cursor_position, window_size, ZOOM_STEP.powf(steps)),
+                    Mode::Sdf | Mode::Path => self.camera.move_by(0.0, 0.0, MOVE_STEP * steps as f32),
+                }
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. },
+                ..
+            } => self.handle_key(key, window_size),
+            WindowEvent::DroppedFile(ref path) => self.open(path),
+            _ => false,
+        }
+    }
+
+    fn handle_key(&mut self, key: VirtualKeyCode, window_size: [f64; 2]) -> bool {
+        match key {
+            VirtualKeyCode::M => {
+                self.mode = match self.mode {
+                    Mode::Fractal => Mode::Sdf,
+                    Mode::Sdf => Mode::Path,
+                    Mode::Path => Mode::Fractal,
+                };
+                tracing::info!("Mode {:?}", self.mode);
+                return true;
+            }
+            VirtualKeyCode::S => {
+                self.save_params(window_size);
+                return false;
+            }
+            _ => {}
+        }
+
+        match self.mode {
+            Mode::Fractal => self.handle_fractal_key(key, window_size),
+            Mode::Sdf | Mode::Path => self.handle_camera_key(key),
+        }
+    }
+
+    fn handle_fractal_key(&mut self, key: VirtualKeyCode, window_size: [f64; 2]) -> bool {
+        let center = [window_size[0] * 0.5, window_size[1] * 0.5];
+        match key {
+            VirtualKeyCode::R | VirtualKeyCode::Home => self.view = self.fractal.kind.default_view(),
+            VirtualKeyCode::Q => self.view.rotate(-ROTATE_STEP),
+            VirtualKeyCode::E => self.view.rotate(ROTATE_STEP),
+            VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => self.view.zoom_at(center, window_size, ZOOM_STEP),
+            VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => self.view.zoom_at(center, window_size, 1.0 / ZOOM_STEP),
+            VirtualKeyCode::RBracket => self.fractal.more_iterations(),
+            VirtualKeyCode::LBracket => self.fractal.fewer_iterations(),
+            VirtualKeyCode::Period => self.fractal.scale_escape_radius(2.0),
+            VirtualKeyCode::Comma => self.fractal.scale_escape_radius(0.5),
+            VirtualKeyCode::V => self.fractal.shift_palette(-PALETTE_SHIFT_STEP),
+            VirtualKeyCode::B => self.fractal.shift_palette(PALETTE_SHIFT_STEP),
+            VirtualKeyCode::C => self.palette_cycling = !self.palette_cycling,
+            VirtualKeyCode::P => {
+                let index = (self.palette_index + 1) % self.palettes.len();
+                match self.palettes[index].upload(&self.memory_allocator) {
+                    Ok(buffer) => self.palette_buffer = buffer,
+                    Err(e) => {
+                        tracing::error!("{}", e);
+                        return false;
+                    }
+                }
+                self.palette_index = index;
+                tracing::info!("Palette {}", self.palettes[index].name);
+            }
+            VirtualKeyCode::J => self.toggle_kind(),
+            VirtualKeyCode::I => self.preview = !self.preview,
+            _ => return false,
+        }
+        tracing::debug!(view = ?self.view, fractal = ?self.fractal, "Parameters changed");
+        true
+    }
+
+    fn handle_camera_key(&mut self, key: VirtualKeyCode) -> bool {
+        match key {
+            VirtualKeyCode::R | VirtualKeyCode::Home => self.camera = Camera::default(),
+            VirtualKeyCode::Up => self.camera.move_by(0.0, 0.0, MOVE_STEP),
+            VirtualKeyCode::Down => self.camera.move_by(0.0, 0.0, -MOVE_STEP),
+            VirtualKeyCode::Left => self.camera.move_by(-MOVE_STEP, 0.0, 0.0),
+            VirtualKeyCode::Right => self.camera.move_by(MOVE_STEP, 0.0, 0.0),
+            VirtualKeyCode::PageUp => self.camera.move_by(0.0, MOVE_STEP, 0.0),
+            VirtualKeyCode::PageDown => self.camera.move_by(0.0, -MOVE_STEP, 0.0),
+            _ => return false,
+        }
+        tracing::debug!(camera = ?self.camera, "Camera moved");
+        true
+    }
+
+    /// The current view, fractal and palette, for renders of `resolution`. The palette offset
+    /// includes the cycled shift, so the parameters render what is on screen.
+    pub fn params(&self, resolution: [u32; 2]) -> Params {
+        let mut fractal = self.fractal;
+        fractal.shift_palette(self.palette_shift);
+        Params {
+            mode: self.mode,
+            palette: self.palettes[self.palette_index].name.clone(),
+            resolution,
+            view: self.view.clone(),
+            fractal,
+            camera: self.camera,
+            sdf: self.sdf.clone(),
+            path: self.path,
+        }
+    }
+
+    /// Restores the view, fractal and palette of a parameter file or a PNG saved with them,
+    /// returns whether that succeeded.
+    fn open(&mut self, path: &Path) -> bool {
+        match self.try_open(path) {
+            Ok(()) => {
+                tracing::info!("Opened {}", path.display());
+                true
+            }
+            Err(e) => {
+                tracing::error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Loads and uploads everything the file needs before changing anything, so that the
+    /// current state stays as it is if any of it fails.
+    fn try_open(&mut self, path: &Path) -> Result<(), Error> {
+        let mut params = Params::load(path)?;
+        let sdf = std::mem::take(&mut params.sdf);
+        let meshes = self.load_meshes(&sdf)?;
+
+        let (palette_index, new_palette) = match self.palettes.iter().position(|palette| palette.name == params.palette) {
+            Some(index) => (index, None),
+            None => (self.palettes.len(), Some(Palette::load(&params.palette)?)),
+        };
+        let palette = match &new_palette {
+            Some(palette) => palette,
+            None => &self.palettes[palette_index],
+        };
+        let palette_buffer = palette.upload(&self.memory_allocator)?;
+
+        if let Some(meshes) = meshes {
+            self.meshes = meshes;
+        }
+        self.sdf = sdf;
+        self.palettes.extend(new_palette);
+        self.palette_buffer = palette_buffer;
+        self.palette_index = palette_index;
+
+        if params.fractal.kind != self.fractal.kind {
+            self.other_view = self.fractal.kind.default_view();
+        }
+        self.mode = params.mode;
+        self.view = params.view;
+        self.fractal = params.fractal;
+        self.palette_shift = 0.0;
+        self.camera = params.camera;
+        self.path = params.path;
+        Ok(())
+    }
+
+    /// Replaces the signed distance scene and loads its meshes if they changed, the previous
+    /// scene stays if they fail to load.
+    pub fn set_sdf(&mut self, sdf: SdfScene) -> Result<(), Error> {
+        if let Some(meshes) = self.load_meshes(&sdf)? {
+            self.meshes = meshes;
+        }
+        self.sdf = sdf;
+        Ok(())
+    }
+
+    /// The meshes of `sdf` uploaded, or `None` if they are those of the current scene.
+    fn load_meshes(&self, sdf: &SdfScene) -> Result<Option<MeshBuffers>, Error> {
+        if sdf.meshes == self.sdf.meshes {
+            return Ok(None);
+        }
+        Ok(Some(Mesh::load_instances(&sdf.meshes)?.upload(&self.memory_allocator)?))
+    }
+
+    /// Writes the current parameters to the next free `sel-NNN.toml` in the working directory.
+    fn save_params(&self, window_size: [f64; 2]) {
+        let path = params::next_free_path("sel", "toml");
+        match self.params([window_size[0] as u32, window_size[1] as u32]).save(&path) {
+            Ok(()) => tracing::info!("Saved parameters to {}", path.display()),
+            Err(e) => tracing::error!("{}", e),
+        }
+    }
+
+    /// Switches between the Mandelbrot and Julia set, each keeps its own view.
+    fn toggle_kind(&mut self) {
+        self.fractal.kind = match self.fractal.kind {
+            FractalKind::Mandelbrot => FractalKind::Julia,
+            FractalKind::Julia => FractalKind::Mandelbrot,
+        };
+        std::mem::swap(&mut self.view, &mut self.other_view);
+    }
+
+    fn showing_preview(&self) -> bool {
+        self.mode == Mode::Fractal && self.preview && self.fractal.kind == FractalKind::Mandelbrot
+    }
+
+    /// Advances time dependent parameters. They only reach the renderer through the per-frame
+    /// uniforms, see `palette_shift`, so nothing here needs the command buffers rebuilt.
+    pub fn update(&mut self, delta: Duration) {
+        if self.palette_cycling && self.mode == Mode::Fractal {
+            self.palette_shift = (self.palette_shift + PALETTE_CYCLE_SPEED * delta.as_secs_f32()).rem_euclid(1.0);
+        }
+    }
+
+    /// Palette lengths to shift the fractal palette by in the current frame.
+    pub fn palette_shift(&self) -> f32 {
+        self.palette_shift
+    }
+
+    /// The current parameters for an image of `extent` pixels.
+    pub fn scene(&self, extent: [u32; 2]) -> Result<Scene, Error> {
+        let mut scene = Scene {
+            palette: self.palette_buffer.clone(),
+            regions: Vec::new(),
+            ray_march: None,
+            path_trace: None,
+        };
+        match self.mode {
+            Mode::Fractal => scene.regions = self.regions(extent)?,
+            Mode::Sdf => {
+                scene.ray_march = Some(RayMarch {
+                    camera: self.camera,
+                    scene: self.sdf.clone(),
+                    objects: self.sdf.upload(&self.memory_allocator)?,
+                    meshes: self.meshes.clone(),
+                });
+            }
+            Mode::Path => {
+                scene.path_trace = Some(PathTrace {
+                    camera: self.camera,
+                    scene: self.sdf.clone(),
+                    settings: self.path,
+                    objects: self.sdf.upload(&self.memory_allocator)?,
+                    meshes: self.meshes.clone(),
+                });
+            }
+        }
+        Ok(scene)
+    }
+
+    /// The fractal, and the Julia preview if it is shown.
+    fn regions(&self, extent: [u32; 2]) -> Result<Vec<Region>, Error> {
+        let [width, height] = extent;
+        // Computed once for all swapchain images, double-float emulation is the least precise
+        // deep precision so any device drawing the region with perturbation finds it
+        let orbit = match Precision::select(&self.view, extent, Precision::DoubleFloat) {
+            Precision::Perturbation => Some(ReferenceOrbit::new(&self.view, &self.fractal).upload(&self.memory_allocator)?),
+            _ => None,
+        };
+        let mut regions = vec![Region {
+            view: self.view.clone(),
+            fractal: self.fractal,
+            orbit,
+            offset: [0, 0],
+            extent,
+        }];
+
+        // The preview is drawn over the bottom right corner after the main region
+        let preview_extent = [width / PREVIEW_SCALE, height / PREVIEW_SCALE];
+        if self.showing_preview() && preview_extent[0] > 0 && preview_extent[1] > 0 {
+            regions.push(Region {
+                view: FractalKind::Julia.default_view(),
+                fractal: FractalParams {
+                    kind: FractalKind::Julia,
+                    julia_c: self.view.pixel_to_plane(self.cursor_position, [width as f64, height as f64]),
+                    ..self.fractal
+                },
+                orbit: None,
+                offset: [
+                    width.saturating_sub(preview_extent[0] + PREVIEW_MARGIN),
+                    height.saturating_sub(preview_extent[1] + PREVIEW_MARGIN),
+                ],
+                extent: preview_extent,
+            });
+        }
+        Ok(regions)
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+
+use crate::view::View;
+
+/// Escape-time formula, the discriminants match the `KIND_*` defines in the compute shaders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(u32)]
+pub enum FractalKind {
+    /// Iterates `z² + c` from the origin with `c` being the pixel
+    Mandelbrot = 0,
+    /// Iterates `z² + c` from the pixel with a fixed `c`
+    Julia = 1,
+}
+
+impl FractalKind {
+    /// View framing the whole set.
+    pub fn default_view(self) -> View {
+        match self {
+            FractalKind::Mandelbrot => View::default(),
+            FractalKind::Julia => View::new([0.0, 0.0], 0.7),
+        }
+    }
+}
+
+/// Parameters of the escape-time iteration in the compute shaders.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FractalParams {
+    pub kind: FractalKind,
+    /// The fixed `c` of the Julia set
+    pub julia_c: [f64; 2],
+    /// Iterations after which a point is considered part of the set
+    pub max_iterations: u32,
+    /// Distance from the origin at which a point is considered escaped
+    pub escape_radius: f32,
+    /// Shift of the palette, in palette lengths
+    pub palette_offset: f32,
+    /// Iterations it takes to run through the whole palette once
+    pub palette_period: f32,
+    /// Colour of the points that never escape
+    pub interior_color: [f32; 3],
+}
+
+impl Default for FractalParams {
+    fn default() -> Self {
+        FractalParams {
+            kind: FractalKind::Mandelbrot,
+            julia_c: [-0.8, 0.156],
+            max_iterations: 200,
+            escape_radius: 4.0,
+            palette_offset: 0.0,
+            palette_period: 64.0,
+            interior_color: [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+impl FractalParams {
+    /// Doubles the iteration budget.
+    pub fn more_iterations(&mut self) {
+        self.max_iterations = self.max_iterations.saturating_mul(2);
+    }
+
+    /// Halves the iteration budget, keeping at least a single iteration.
+    pub fn fewer_iterations(&mut self) {
+        self.max_iterations = (self.max_iterations / 2).max(1);
+    }
+
+    /// Scales the escape radius, which never drops below 2 as smaller radii cut into the set.
+    pub fn scale_escape_radius(&mut self, factor: f32) {
+        self.escape_radius = (self.escape_radius * factor).max(2.0);
+    }
+
+    /// Shifts the palette, wrapping around after a whole palette length.
+    pub fn shift_palette(&mut self, amount: f32) {
+        self.palette_offset = (self.palette_offset + amount).rem_euclid(1.0);
+    }
+}
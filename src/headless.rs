@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use image::RgbaImage;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferUsage, CopyImageToBufferInfo, PrimaryAutoCommandBuffer, SecondaryAutoCommandBuffer};
+use vulkano::image::StorageImage;
+use vulkano::image::view::ImageView;
+use vulkano::format::ClearColorValue;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage};
+use vulkano::sync::{self, GpuFuture};
+
+use crate::camera::Camera;
+use crate::compute_rays_pipeline::ComputeRaysPipeline;
+use crate::error::Error;
+use crate::fractal::FractalParams;
+use crate::mesh::Mesh;
+use crate::palette::Palette;
+use crate::params::{Mode, Params};
+use crate::pass::{FrameUniforms, PathTrace, RayMarch};
+use crate::path_trace::PathSettings;
+use crate::renderer::{Context, DEFAULT_CLEAR_COLOR};
+use crate::sdf::SdfScene;
+use crate::view::View;
+use crate::vulkan;
+
+/// Renders single frames without a window or swapchain and reads them back to the host.
+pub struct HeadlessRenderer {
+    context: Context,
+    compute_pipeline: ComputeRaysPipeline,
+    clear_color: [f32; 4],
+}
+
+impl HeadlessRenderer {
+    pub fn new(context: Context) -> Result<HeadlessRenderer, Error> {
+        let compute_pipeline = ComputeRaysPipeline::new(
+            context.queue.clone(),
+            context.command_buffer_allocator.clone(),
+            context.memory_allocator.clone(),
+        )?;
+
+        Ok(HeadlessRenderer {
+            context,
+            compute_pipeline,
+            clear_color: DEFAULT_CLEAR_COLOR,
+        })
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Colour of the parts of the image no fractal is drawn to.
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+    }
+
+    /// Renders `view` into an image of `dimensions` pixels and waits for it.
+    pub fn render(
+        &self,
+        dimensions: [u32; 2],
+        view: &View,
+        fractal: &FractalParams,
+        palette: &Palette,
+    ) -> Result<RgbaImage, Error> {
+        self.render_with(dimensions, |image_view| {
+            let palette = palette.upload(&self.context.memory_allocator)?;
+            let uniforms = FrameUniforms::default().upload(&self.context.memory_allocator)?;
+            self.compute_pipeline.draw(image_view, palette, uniforms, view, fractal)
+        })
+    }
+
+    /// Ray marches `scene` as seen by `camera` into an image of `dimensions` pixels and waits for it.
+    pub fn render_sdf(&self, dimensions: [u32; 2], camera: &Camera, scene: &SdfScene) -> Result<RgbaImage, Error> {
+        let ray_march = RayMarch {
+            camera: *camera,
+            scene: scene.clone(),
+            objects: scene.upload(&self.context.memory_allocator)?,
+            meshes: Mesh::load_instances(&scene.meshes)?.upload(&self.context.memory_allocator)?,
+        };
+        self.render_with(dimensions, |image_view| {
+            self.compute_pipeline.draw_ray_march(image_view, &ray_march, [0, 0], dimensions)
+        })
+    }
+
+    /// Path traces `scene` as seen by `camera` into an image of `dimensions` pixels, averaging
+    /// `settings.samples` samples per pixel, and waits for it.
+    pub fn render_path_trace(
+        &self,
+        dimensions: [u32; 2],
+        camera: &Camera,
+        scene: &SdfScene,
+        settings: &PathSettings,
+    ) -> Result<RgbaImage, Error> {
+        let context = &self.context;
+        let path_trace = PathTrace {
+            camera: *camera,
+            scene: scene.clone(),
+            settings: *settings,
+            objects: scene.upload(&context.memory_allocator)?,
+            meshes: Mesh::load_instances(&scene.meshes)?.upload(&context.memory_allocator)?,
+        };
+        let (_, accumulation_view) = vulkan::get_accumulation_image(
+            &context.memory_allocator,
+            context.queue.clone(),
+            dimensions,
+        )?;
+        let uniforms = FrameUniforms::default().upload(&context.memory_allocator)?;
+
+        self.render_with(dimensions, |image_view| {
+            let samples = settings.samples.max(1);
+            let draw = |sample: u32| {
+                // The previous sample has finished with the index
+                uniforms.write().map_err(Error::BufferAccess)?.frame_index = sample;
+                self.compute_pipeline.draw_path_trace(
+                    image_view.clone(),
+                    accumulation_view.clone(),
+                    uniforms.clone(),
+                    &path_trace,
+                    [0, 0],
+                    dimensions,
+                )
+            };
+
+            // Each sample is submitted on its own, the last one is read back with the image
+            for sample in 0..samples - 1 {
+                let mut builder = self.primary_builder()?;
+                builder.execute_commands(draw(sample)?)?;
+                self.submit(builder.build()?)?;
+                tracing::debug!("Sample {} of {} done", sample + 1, samples);
+            }
+            draw(samples - 1)
+        })
+    }
+
+    /// Renders what `params` describe at their resolution, the palette is only used for fractals.
+    pub fn render_params(&self, params: &Params, palette: &Palette) -> Result<RgbaImage, Error> {
+        match params.mode {
+            Mode::Fractal => self.render(params.resolution, &params.view, &params.fractal, palette),
+            Mode::Sdf => self.render_sdf(params.resolution, &params.camera, &params.sdf),
+            Mode::Path => self.render_path_trace(params.resolution, &params.camera, &params.sdf, &params.path),
+        }
+    }
+
+    /// Clears an image, executes the commands `draw` records into it and reads it back.
+    fn render_with(
+        &self,
+        dimensions: [u32; 2],
+        draw: impl FnOnce(Arc<ImageView<StorageImage>>) -> Result<SecondaryAutoCommandBuffer, Error>,
+    ) -> Result<RgbaImage, Error> {
+        let context = &self.context;
+
+        // Image
+        let (image, image_view) = vulkan::get_image(
+            &context.memory_allocator,
+            context.queue.clone(),
+            dimensions,
+        )?;
+        let [width, height] = dimensions;
+
+        // Host visible buffer to read the image back into, the size overflows u32 at 4 GiB
+        let buffer = Buffer::new_slice::<u8>(
+            &context.memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Download,
+                ..Default::default()
+            },
+            width as u64 * height as u64 * 4,
+        )?;
+
+        let mut builder = self.primary_builder()?;
+        builder
+            .clear_color_image(ClearColorImageInfo {
+                clear_value: ClearColorValue::Float(self.clear_color),
+                ..ClearColorImageInfo::image(image.clone())
+            })?
+            .execute_commands(draw(image_view)?)?
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, buffer.clone()))?;
+        self.submit(builder.build()?)?;
+
+        let content = buffer.read().map_err(Error::BufferAccess)?;
+        RgbaImage::from_raw(width, height, content.to_vec()).ok_or(Error::Readback(dimensions))
+    }
+
+    fn primary_builder(&self) -> Result<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, Error> {
+        Ok(AutoCommandBufferBuilder::primary(
+            self.context.command_buffer_allocator.as_ref(),
+            self.context.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?)
+    }
+
+    /// Executes `command_buffer` and waits for it to finish.
+    fn submit(&self, command_buffer: PrimaryAutoCommandBuffer) -> Result<(), Error> {
+        sync::now(self.context.device.clone())
+            .then_execute(self.context.queue.clone(), command_buffer)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+        Ok(())
+    }
+}
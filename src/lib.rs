@@ -0,0 +1,34 @@
+//! Escape-time fractals and ray marched or path traced signed distance scenes, with triangle
+//! meshes from OBJ and glTF files, rendered with Vulkan compute shaders.
+//!
+//! A `Context` owns the device, a `Renderer` draws a `Scene` into a window through a list of
+//! `Pass`es, and a `HeadlessRenderer` renders frames offscreen without a window.
+
+pub mod vulkan;
+pub mod draw_pipeline;
+pub mod compute_rays_pipeline;
+pub mod animation;
+pub mod camera;
+pub mod error;
+pub mod explorer;
+pub mod fractal;
+pub mod headless;
+pub mod mesh;
+pub mod palette;
+pub mod params;
+pub mod pass;
+pub mod path_trace;
+pub mod perturbation;
+pub mod poster;
+pub mod precision;
+pub mod renderer;
+pub mod screenshot;
+pub mod sdf;
+pub mod view;
+
+pub use error::Error;
+pub use headless::HeadlessRenderer;
+pub use params::Params;
+pub use pass::{Frame, Pass, PassStage, PathTrace, RayMarch, Region, Scene};
+pub use poster::Poster;
+pub use renderer::{Context, Renderer};
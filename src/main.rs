@@ -1,206 +1,603 @@
-pub mod vulkan;
-mod draw_pipeline;
-mod compute_rays_pipeline;
-
-use std::sync::Arc;
-use vulkano::memory::allocator::{StandardMemoryAllocator, MemoryUsage, AllocationCreateInfo};
-use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
-use vulkano::sync::{self, FlushError, GpuFuture};
-use vulkano::swapchain;
-
-use tracing_subscriber;
-use tracing_subscriber::filter::FilterExt;
-use vulkano::command_buffer::{AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferUsage, PrimaryAutoCommandBuffer, RenderPassBeginInfo, SubpassContents};
-use vulkano::device::Queue;
-use vulkano::format::{ClearColorValue, Format};
-use vulkano::image::{ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage};
-use vulkano::image::sys::Image;
-use vulkano::image::view::ImageView;
-
-use winit::event::{Event, WindowEvent};
-use winit::event_loop::EventLoop;
-use winit::window::WindowBuilder;
-use winit::window::Window;
-use winit::event_loop::ControlFlow;
-
-use vulkano::pipeline::graphics::viewport::Viewport;
-use vulkano::render_pass::{Framebuffer, Subpass};
-use vulkano::swapchain::{AcquireError, SwapchainCreateInfo, SwapchainCreationError, SwapchainPresentInfo};
-use vulkano::sync::future::FenceSignalFuture;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use clap::{Parser, Subcommand};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, Event, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::{Window, WindowBuilder};
 
 use vulkano_win::VkSurfaceBuild;
-use crate::compute_rays_pipeline::ComputeRaysPipeline;
-use crate::draw_pipeline::DrawPipeline;
-use crate::vulkan::get_framebuffers;
-
-pub fn get_image(memory_allocator: &StandardMemoryAllocator, queue: Arc<Queue>) -> (Arc<StorageImage>, Arc<ImageView<StorageImage>>) {
-    let mut image = StorageImage::with_usage(
-        memory_allocator,
-        ImageDimensions::Dim2d {
-            width: 1024,
-            height: 1024,
-            array_layers: 1,
-        },
-        Format::R8G8B8A8_UNORM,
-        ImageUsage::TRANSFER_SRC
-            | ImageUsage::TRANSFER_DST
-            | ImageUsage::SAMPLED
-            | ImageUsage::STORAGE,
-        ImageCreateFlags::empty(),
-        Some(queue.queue_family_index()),
-    ).unwrap();
-
-    let view = ImageView::new_default(image.clone()).unwrap();
-
-    return (image, view);
+use sel::animation::{frame_path, Animation, Loop, LoopEncoder, Y4mWriter};
+use sel::explorer::Explorer;
+use sel::fractal::FractalKind;
+use sel::palette::Palette;
+use sel::precision::Fixed;
+use sel::renderer::DEFAULT_CLEAR_COLOR;
+use sel::view::View;
+use sel::vulkan::{self, DeviceSelector, LayerConfig};
+use sel::params::{self, Mode};
+use sel::screenshot::{self, CaptureSource};
+use sel::{Context, Error, HeadlessRenderer, Params, Poster, Renderer};
+
+#[derive(Parser)]
+#[command(about = "Rust + Vulkan experiments", args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print the devices like `info` and exit
+    #[arg(long)]
+    list_devices: bool,
+
+    /// Options of `run`, which is the default
+    #[command(flatten)]
+    run: RunArgs,
 }
 
-fn build_command_buffers(
-    command_buffer_allocator: &StandardCommandBufferAllocator,
-    queue: &Arc<Queue>,
-    framebuffers: &Vec<Arc<Framebuffer>>,
-    draw_pipeline: &Arc<DrawPipeline>,
-    compute_pipeline: &Arc<ComputeRaysPipeline>,
-    viewport: &Viewport,
-    image: &Arc<StorageImage>,
-    image_view: &Arc<ImageView<StorageImage>>
-) -> Vec<Arc<PrimaryAutoCommandBuffer>> {
-    framebuffers
-        .iter()
-        .map(|framebuffer| {
-            let mut builder = AutoCommandBufferBuilder::primary(
-                command_buffer_allocator,
-                queue.queue_family_index(),
-                CommandBufferUsage::MultipleSubmit, // don't forget to write the correct buffer usage
-            ).unwrap();
-
-            builder.clear_color_image(ClearColorImageInfo {
-                clear_value: ClearColorValue::Float([0.0, 0.0, 1.0, 1.0]),
-                ..ClearColorImageInfo::image(image.clone())
-            }).unwrap();
-
-            // Execute the compute pipeline
-            // TODO: Perform compute shader without access error
-            // builder.execute_commands(
-            //     compute_pipeline.draw(image_view.clone())
-            // ).unwrap();
-
-            // Start a renderpass for the framebuffer
-            builder.begin_render_pass(
-                RenderPassBeginInfo {
-                    clear_values: vec![Some([0.1, 0.1, 0.1, 1.0].into())],
-                    ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
-                },
-                SubpassContents::SecondaryCommandBuffers,
-            ).unwrap();
+#[derive(Subcommand)]
+enum Command {
+    /// Explore the fractal in a window
+    Run(RunArgs),
+    /// Render a single frame offscreen and save it, no display needed
+    Render(RenderArgs),
+    /// Render an image of any size in tiles, supersampled, resuming if it was interrupted
+    Poster(PosterArgs),
+    /// Render the frames of a keyframe animation as PNGs, or as a Y4M video stream to stdout
+    Animate(AnimateArgs),
+    /// Render a short repeating animation as an animated GIF or PNG
+    Loop(LoopArgs),
+    /// Time offscreen renders
+    Bench(BenchArgs),
+    /// Print the devices with their capabilities, memory heaps, queue families and extensions
+    Info(VulkanArgs),
+}
+
+#[derive(clap::Args)]
+struct RunArgs {
+    /// File name of screenshots taken with F12, with placeholders {time}, {source}, {kind},
+    /// {zoom}, {iterations}, {width} and {height}
+    #[arg(long, value_name = "TEMPLATE", default_value = screenshot::DEFAULT_TEMPLATE)]
+    screenshot: String,
+
+    #[command(flatten)]
+    scene: SceneArgs,
+
+    #[command(flatten)]
+    vulkan: VulkanArgs,
+}
+
+#[derive(clap::Args)]
+struct RenderArgs {
+    /// Output file, the format follows the extension
+    #[arg(short, long, default_value = "sel.png")]
+    output: PathBuf,
+
+    #[command(flatten)]
+    scene: SceneArgs,
+
+    #[command(flatten)]
+    vulkan: VulkanArgs,
+}
+
+#[derive(clap::Args)]
+struct PosterArgs {
+    /// Output file, .png or .tif
+    #[arg(short, long, default_value = "poster.png")]
+    output: PathBuf,
+
+    /// Average NxN samples per pixel
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    supersample: u32,
+
+    /// Width and height of the tiles in output pixels
+    #[arg(long, value_name = "PIXELS", default_value_t = 1024)]
+    tile: u32,
+
+    #[command(flatten)]
+    scene: SceneArgs,
+
+    #[command(flatten)]
+    vulkan: VulkanArgs,
+}
+
+#[derive(clap::Args)]
+struct AnimateArgs {
+    /// TOML file with a list of [[keyframes]], each with a `time` in seconds and any of
+    /// `center`, `zoom`, `rotation`, `palette_offset` and `max_iterations`
+    keyframes: PathBuf,
+
+    /// Frame file name with a {frame} placeholder, or - to write Y4M to stdout
+    #[arg(short, long, default_value = "frame-{frame}.png")]
+    output: String,
+
+    /// Frames per second
+    #[arg(long, default_value_t = 30.0, value_parser = parse_fps)]
+    fps: f64,
+
+    #[command(flatten)]
+    scene: SceneArgs,
+
+    #[command(flatten)]
+    vulkan: VulkanArgs,
+}
+
+#[derive(clap::Args)]
+struct LoopArgs {
+    /// Output file, .gif or .png
+    #[arg(short, long, default_value = "loop.gif")]
+    output: PathBuf,
+
+    /// Number of frames in one loop
+    #[arg(long, default_value_t = 60)]
+    frames: u32,
+
+    /// Frames per second
+    #[arg(long, default_value_t = 20.0, value_parser = parse_fps)]
+    fps: f64,
+
+    /// Times the palette cycles through per loop, negative cycles backwards
+    #[arg(long, default_value_t = 1, allow_negative_numbers = true)]
+    palette_cycles: i32,
+
+    /// Move the Julia constant of --julia around a circle of this radius
+    #[arg(long, value_name = "RADIUS", default_value_t = 0.0)]
+    julia_orbit: f64,
+
+    #[command(flatten)]
+    scene: SceneArgs,
+
+    #[command(flatten)]
+    vulkan: VulkanArgs,
+}
+
+#[derive(clap::Args)]
+struct BenchArgs {
+    /// Number of timed frames, after one untimed warm-up frame
+    #[arg(long, default_value_t = 20)]
+    frames: u32,
+
+    #[command(flatten)]
+    scene: SceneArgs,
+
+    #[command(flatten)]
+    vulkan: VulkanArgs,
+}
+
+/// What to render, shared by all subcommands that render. Options override the parameter file.
+#[derive(clap::Args)]
+struct SceneArgs {
+    /// Start from the parameters in a TOML file as saved with S in the window, or embedded in a
+    /// PNG saved by `render`
+    #[arg(long, value_name = "FILE")]
+    params: Option<PathBuf>,
+
+    /// Render the escape-time fractal, or ray march or path trace the signed distance scene of
+    /// the parameter file [default: fractal]
+    #[arg(long, value_name = "fractal|sdf|path")]
+    mode: Option<Mode>,
+
+    /// Paths averaged per pixel by the path tracer [default: 256]
+    #[arg(long)]
+    samples: Option<u32>,
+
+    /// Seed of the random numbers of the path tracer [default: 0]
+    #[arg(long)]
+    seed: Option<u32>,
+
+    /// Width of the image, or of the window at startup [default: 1280]
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// Height of the image, or of the window at startup [default: 720]
+    #[arg(long)]
+    height: Option<u32>,
+
+    /// Maximum number of iterations per pixel [default: 200]
+    #[arg(long)]
+    iterations: Option<u32>,
+
+    /// Distance from the origin at which the iteration stops [default: 4]
+    #[arg(long)]
+    escape_radius: Option<f32>,
+
+    /// Built-in palette (classic, fire, ocean, grayscale) or a .map, .gpl or .ggr file
+    /// [default: classic]
+    #[arg(long)]
+    palette: Option<String>,
+
+    /// Iterations it takes to run through the whole palette once [default: 64]
+    #[arg(long)]
+    palette_period: Option<f32>,
+
+    /// Shift of the palette, in palette lengths [default: 0]
+    #[arg(long)]
+    palette_offset: Option<f32>,
+
+    /// Colour of the points that never escape [default: 0 0 0]
+    #[arg(long, num_args = 3, value_names = ["R", "G", "B"])]
+    interior_color: Option<Vec<f32>>,
+
+    /// Render the Julia set of the constant RE IM instead of the Mandelbrot set
+    #[arg(long, num_args = 2, value_names = ["RE", "IM"], allow_negative_numbers = true)]
+    julia: Option<Vec<f64>>,
+
+    /// Point at the center of the view, decimals beyond double precision are kept
+    #[arg(long, num_args = 2, value_names = ["X", "Y"], allow_negative_numbers = true)]
+    center: Option<Vec<Fixed>>,
+
+    /// Magnification, 1 fits the height of the view to 2 units
+    #[arg(long, value_parser = parse_zoom)]
+    zoom: Option<f64>,
+
+    /// Rotation of the view in degrees, counterclockwise [default: 0]
+    #[arg(long, allow_negative_numbers = true)]
+    rotation: Option<f64>,
+
+    /// Colour of the parts of the image no fractal is drawn to
+    #[arg(long, num_args = 3, value_names = ["R", "G", "B"], default_values_t = DEFAULT_CLEAR_COLOR[..3].to_vec())]
+    clear_color: Vec<f32>,
+}
+
+/// Device and layer selection.
+#[derive(clap::Args)]
+struct VulkanArgs {
+    /// Enable the Khronos validation layer, see also SEL_VALIDATION
+    #[arg(long)]
+    validation: bool,
+
+    /// Enable an extra Vulkan layer by name, can be repeated, see also SEL_LAYERS
+    #[arg(long = "layer", value_name = "NAME")]
+    layers: Vec<String>,
+
+    /// Use the device at this index of `info`, or whose name contains this text, see also
+    /// SEL_DEVICE
+    #[arg(long, value_name = "INDEX|NAME")]
+    device: Option<DeviceSelector>,
+}
+
+impl SceneArgs {
+    /// The parameter file or the defaults, with the options applied.
+    fn params(&self) -> Result<Params, Error> {
+        let mut params = match &self.params {
+            Some(path) => Params::load(path)?,
+            None => Params::default(),
+        };
+
+        let fractal = &mut params.fractal;
+        if let Some(&[re, im]) = self.julia.as_deref() {
+            if fractal.kind != FractalKind::Julia {
+                fractal.kind = FractalKind::Julia;
+                params.view = FractalKind::Julia.default_view();
+            }
+            fractal.julia_c = [re, im];
+        }
+        if let Some(iterations) = self.iterations {
+            fractal.max_iterations = iterations.max(1);
+        }
+        if let Some(escape_radius) = self.escape_radius {
+            fractal.escape_radius = escape_radius;
+        }
+        if let Some(palette_period) = self.palette_period {
+            fractal.palette_period = palette_period;
+        }
+        if let Some(palette_offset) = self.palette_offset {
+            fractal.palette_offset = palette_offset.rem_euclid(1.0);
+        }
+        if let Some(&[r, g, b]) = self.interior_color.as_deref() {
+            fractal.interior_color = [r, g, b];
+        }
+
+        let view = &mut params.view;
+        if let Some(zoom) = self.zoom {
+            view.zoom = zoom;
+        }
+        let fraction_limbs = View::fraction_limbs(view.zoom);
+        if let Some([x, y]) = self.center.as_deref() {
+            view.center = [x.clone(), y.clone()];
+        }
+        view.center = view.center.clone().map(|c| c.with_fraction_limbs(fraction_limbs));
+        if let Some(rotation) = self.rotation {
+            view.rotation = rotation.to_radians();
+        }
+
+        if let Some(mode) = self.mode {
+            params.mode = mode;
+        }
+        if let Some(samples) = self.samples {
+            params.path.samples = samples.max(1);
+        }
+        if let Some(seed) = self.seed {
+            params.path.seed = seed;
+        }
+        if let Some(palette) = &self.palette {
+            params.palette = palette.clone();
+        }
+        if let Some(width) = self.width {
+            params.resolution[0] = width;
+        }
+        if let Some(height) = self.height {
+            params.resolution[1] = height;
+        }
 
-            // Bind the pipeline
-            builder.execute_commands(
-                draw_pipeline.draw(viewport)
-            ).unwrap();
+        params.validate()?;
+        Ok(params)
+    }
 
-            // End renderpass
-            builder.end_render_pass().unwrap();
+    fn clear_color(&self) -> [f32; 4] {
+        [self.clear_color[0], self.clear_color[1], self.clear_color[2], 1.0]
+    }
+}
+
+impl VulkanArgs {
+    /// The CLI flags add to the configuration from the environment.
+    fn layer_config(&self) -> LayerConfig {
+        let mut config = LayerConfig::from_env();
+        config.validation |= self.validation;
+        config.extra_layers.extend(self.layers.iter().cloned());
+        config
+    }
+
+    /// The CLI flag takes precedence over the environment.
+    fn device(&self) -> Option<DeviceSelector> {
+        self.device.clone().or_else(DeviceSelector::from_env)
+    }
+
+    fn headless_renderer(&self, clear_color: [f32; 4]) -> Result<HeadlessRenderer, Error> {
+        let context = Context::headless(&self.layer_config(), self.device().as_ref())?;
+        let mut renderer = HeadlessRenderer::new(context)?;
+        renderer.set_clear_color(clear_color);
+        Ok(renderer)
+    }
+}
+
+/// Tiles, animations and loops are only implemented for fractals.
+fn fractal_only(params: &Params) -> Result<(), String> {
+    match params.mode {
+        Mode::Fractal => Ok(()),
+        Mode::Sdf | Mode::Path => Err("only fractals can be rendered this way, not signed distance scenes".to_owned()),
+    }
+}
+
+/// A finite magnification above 0.
+fn parse_zoom(value: &str) -> Result<f64, String> {
+    let zoom: f64 = value.parse().map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    if zoom > 0.0 && zoom.is_finite() {
+        Ok(zoom)
+    } else {
+        Err("the zoom has to be a finite number above 0".to_owned())
+    }
+}
 
-            Arc::new(builder.build().unwrap())
-        })
-        .collect()
+/// A finite frame rate above 0.
+fn parse_fps(value: &str) -> Result<f64, String> {
+    let fps: f64 = value.parse().map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    if fps > 0.0 && fps.is_finite() {
+        Ok(fps)
+    } else {
+        Err("the frame rate has to be a finite number above 0".to_owned())
+    }
+}
+
+fn window_size(window: &Window) -> [f64; 2] {
+    let size = window.inner_size();
+    [size.width as f64, size.height as f64]
 }
 
 fn main() {
 
-    // Logging setup
-    tracing_subscriber::fmt::init();
+    // Logging setup, RUST_LOG overrides the level, e.g. `RUST_LOG=info,vulkan=trace`. Logs go to
+    // stderr, stdout may carry a video stream.
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
 
-    // Vulkan setup
-    let instance = vulkan::create_instance();
+    let cli = Cli::parse();
+    let command = match cli.command {
+        Some(command) => command,
+        None if cli.list_devices => Command::Info(cli.run.vulkan),
+        None => Command::Run(cli.run),
+    };
+    let result = match command {
+        Command::Run(args) => run(args),
+        Command::Render(args) => render(args),
+        Command::Poster(args) => poster(args),
+        Command::Animate(args) => animate(args),
+        Command::Loop(args) => render_loop(args),
+        Command::Bench(args) => bench(args),
+        Command::Info(args) => info(args),
+    };
 
-    // Window - creates vk surface
-    let event_loop = EventLoop::new();
-    let surface = WindowBuilder::new()
-        .build_vk_surface(&event_loop, instance.clone())
-        .unwrap();
-    let window = surface
-        .object()
-        .unwrap()
-        .clone()
-        .downcast::<Window>()
-        .unwrap();
-
-    window.set_title("Sel");
-
-    // Vulkan devices - takes window surface
-    let (physical_device, queue_family_index) = vulkan::create_physical_device(instance.clone(), surface.clone());
-    let (device, mut queues) = vulkan::create_device(physical_device.clone(), queue_family_index);
-    let queue = queues.next().unwrap();
-    let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
-
-    // Swapchain
-    let (mut swapchain, images) = vulkan::create_swapchain(
-        physical_device.clone(),
-        device.clone(),
-        window.clone(),
-        surface.clone(),
-    );
+    if let Err(e) = result {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn render(args: RenderArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let params = args.scene.params()?;
+    let palette = Palette::load(&params.palette).map_err(Error::from)?;
+    let renderer = args.vulkan.headless_renderer(args.scene.clear_color())?;
+
+    let image = renderer.render_params(&params, &palette)?;
+    params::save_image(&args.output, &image, &params)?;
+
+    tracing::info!("Saved render to {}", args.output.display());
+    Ok(())
+}
 
-    // Render pass
-    let render_pass = vulkan::get_render_pass(device.clone(), &swapchain);
-    let framebuffers = get_framebuffers(&images, &render_pass);
+fn poster(args: PosterArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let params = args.scene.params()?;
+    fractal_only(&params)?;
+    let palette = Palette::load(&params.palette).map_err(Error::from)?;
+    let renderer = args.vulkan.headless_renderer(args.scene.clear_color())?;
 
-    let mut viewport = Viewport {
-        origin: [0.0, 0.0],
-        dimensions: window.inner_size().into(),
-        depth_range: 0.0..1.0,
+    let poster = Poster {
+        params,
+        supersampling: args.supersample,
+        tile_size: args.tile,
     };
+    poster.render(&renderer, &palette, &args.output)?;
+    Ok(())
+}
 
-    // Image
-    let (image, image_view) = get_image(
-        &memory_allocator,
-        queue.clone(),
-    );
+fn animate(args: AnimateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let params = args.scene.params()?;
+    fractal_only(&params)?;
+    if args.output != "-" {
+        // Fails without a {frame} placeholder, before anything is rendered
+        frame_path(&args.output, 0)?;
+    }
+    let palette = Palette::load(&params.palette).map_err(Error::from)?;
+    let animation = Animation::load(&args.keyframes, params.clone())?;
+    let renderer = args.vulkan.headless_renderer(args.scene.clear_color())?;
+
+    let fps = args.fps;
+    let frames = animation.frame_count(fps);
+    let mut y4m = match args.output.as_str() {
+        "-" => Some(Y4mWriter::new(BufWriter::new(io::stdout().lock()), params.resolution, fps)?),
+        _ => None,
+    };
 
-    // Command buffers
-    let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
-        device.clone(),
-        StandardCommandBufferAllocatorCreateInfo::default(),
-    ));
-
-    // Compute pipeline
-    let compute_pipeline = Arc::new(
-        ComputeRaysPipeline::new(
-            queue.clone(),
-            render_pass.clone(),
-            command_buffer_allocator.clone()
-        )
-    );
+    let start = Instant::now();
+    for frame in 0..frames {
+        let params = animation.params_at(frame as f64 / fps);
+        let image = renderer.render(params.resolution, &params.view, &params.fractal, &palette)?;
+        match &mut y4m {
+            Some(writer) => writer.write_frame(&image)?,
+            None => params::save_image(&frame_path(&args.output, frame)?, &image, &params)?,
+        }
 
-    // Draw pipeline
-    let draw_pipeline = Arc::new(
-        draw_pipeline::DrawPipeline::new(
-            queue.clone(),
-            render_pass.clone(),
-            command_buffer_allocator.clone(),
-        )
-    );
+        let eta = start.elapsed() / (frame + 1) * (frames - frame - 1);
+        tracing::info!("Frame {} of {} done, about {}s left", frame + 1, frames, eta.as_secs());
+    }
+
+    if let Some(writer) = y4m {
+        writer.finish()?;
+    }
+    Ok(())
+}
+
+fn render_loop(args: LoopArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let params = args.scene.params()?;
+    fractal_only(&params)?;
+    let palette = Palette::load(&params.palette).map_err(Error::from)?;
+    let renderer = args.vulkan.headless_renderer(args.scene.clear_color())?;
+
+    if args.julia_orbit != 0.0 && params.fractal.kind != FractalKind::Julia {
+        tracing::warn!("The Julia constant only moves in Julia sets, add --julia RE IM");
+    }
+
+    let fps = args.fps;
+    let frames = args.frames.max(1);
+    let animation = Loop {
+        params,
+        frames,
+        palette_cycles: args.palette_cycles,
+        julia_orbit: args.julia_orbit,
+    };
+    let mut encoder = LoopEncoder::create(&args.output, &animation.params, frames, fps)?;
+    for frame in 0..frames {
+        let params = animation.params_at(frame);
+        encoder.write_frame(renderer.render(params.resolution, &params.view, &params.fractal, &palette)?)?;
+        tracing::info!("Frame {} of {} done", frame + 1, frames);
+    }
+    encoder.finish()?;
+
+    tracing::info!("Saved loop to {}", args.output.display());
+    Ok(())
+}
 
-    let mut command_buffers = build_command_buffers(
-        &command_buffer_allocator,
-        &queue,
-        &framebuffers,
-        &draw_pipeline,
-        &compute_pipeline,
-        &viewport,
-        &image,
-        &image_view,
+fn bench(args: BenchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let params = args.scene.params()?;
+    let palette = Palette::load(&params.palette).map_err(Error::from)?;
+    let renderer = args.vulkan.headless_renderer(args.scene.clear_color())?;
+    let render = || renderer.render_params(&params, &palette);
+
+    // Pipeline caches and allocations settle during the first frame
+    render()?;
+
+    let mut times = Vec::new();
+    for _ in 0..args.frames.max(1) {
+        let start = Instant::now();
+        render()?;
+        times.push(start.elapsed());
+    }
+
+    let [width, height] = params.resolution;
+    let mean = times.iter().sum::<Duration>() / times.len() as u32;
+    let min = times.iter().min().unwrap();
+    let max = times.iter().max().unwrap();
+    let megapixels = (width as f64 * height as f64) / 1e6;
+    // The iteration budget only applies to fractals
+    let workload = match params.mode {
+        Mode::Fractal => format!("{} iterations", params.fractal.max_iterations),
+        Mode::Sdf => "sdf".to_owned(),
+        Mode::Path => "path".to_owned(),
+    };
+    println!(
+        "{} frames of {}x{}, {}: mean {:.2?}, min {:.2?}, max {:.2?}, {:.1} Mpixel/s",
+        times.len(),
+        width,
+        height,
+        workload,
+        mean,
+        min,
+        max,
+        megapixels / mean.as_secs_f64(),
     );
+    Ok(())
+}
 
-    // Event loop
-    let mut window_resized = false;
-    let mut recreate_swapchain = false;
+fn info(args: VulkanArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let instance = vulkan::create_instance(false, &args.layer_config())?;
+    println!("Vulkan {}", instance.api_version());
+    for (i, device) in vulkan::enumerate_devices(&instance)?.iter().enumerate() {
+        println!("{}: {}", i, vulkan::describe_device(device));
+    }
+    Ok(())
+}
+
+fn run(args: RunArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let params = args.scene.params()?;
+
+    // Vulkan setup
+    let instance = vulkan::create_instance(true, &args.vulkan.layer_config())?;
 
-    let frames_in_flight = images.len();
-    let mut fences: Vec<Option<Arc<FenceSignalFuture<_>>>> = vec![None; frames_in_flight];
-    let mut previous_fence_i = 0;
+    // Window - creates vk surface
+    let event_loop = EventLoop::new();
+    let surface = WindowBuilder::new()
+        .with_title("Sel")
+        .with_inner_size(PhysicalSize::new(params.resolution[0], params.resolution[1]))
+        .build_vk_surface(&event_loop, instance.clone())
+        .map_err(Error::from)?;
+
+    let context = Context::windowed(instance, &surface, args.vulkan.device().as_ref())?;
+    let mut explorer = Explorer::new(
+        params.fractal,
+        Palette::load(&params.palette).map_err(Error::from)?,
+        context.memory_allocator.clone(),
+    )?;
+    explorer.mode = params.mode;
+    explorer.view = params.view;
+    explorer.camera = params.camera;
+    explorer.set_sdf(params.sdf)?;
+    explorer.path = params.path;
+    let mut renderer = Renderer::new(context, surface)?;
+    renderer.set_clear_color(args.scene.clear_color());
+
+    // Event loop
+    let mut params_changed = false;
+    let mut last_frame = Instant::now();
+    let mut modifiers = ModifiersState::empty();
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -208,98 +605,48 @@ fn main() {
                 *control_flow = ControlFlow::Exit;
             }
             Event::WindowEvent { event: WindowEvent::Resized(_), .. } => {
-                window_resized = true;
+                renderer.window_resized();
+            }
+            Event::WindowEvent { event: WindowEvent::ModifiersChanged(state), .. } => {
+                modifiers = state;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    input: KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F12),
+                        ..
+                    },
+                    ..
+                },
+                ..
+            } => {
+                // Shift includes what the graphics passes draw over the image
+                let source = if modifiers.shift() { CaptureSource::Window } else { CaptureSource::Image };
+                let params = explorer.params(renderer.image_extent());
+                let path = renderer.screenshot_path(&args.screenshot, &params, source, SystemTime::now());
+                renderer.capture(source, path, params);
+            }
+            Event::WindowEvent { event, .. } => {
+                params_changed |= explorer.handle_event(&event, window_size(renderer.window()));
             }
             Event::MainEventsCleared => {
-                if window_resized || recreate_swapchain {
-                    recreate_swapchain = false;
-
-                    let new_dimensions = window.inner_size();
-
-                    let (new_swapchain, new_images) = match swapchain.recreate(SwapchainCreateInfo {
-                        image_extent: new_dimensions.into(), // here, "image_extend" will correspond to the window dimensions
-                        ..swapchain.create_info()
-                    }) {
-                        Ok(r) => r,
-                        // This error tends to happen when the user is manually resizing the window.
-                        // Simply restarting the loop is the easiest way to fix this issue.
-                        Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return,
-                        Err(e) => panic!("failed to recreate swapchain: {}", e),
-                    };
-                    swapchain = new_swapchain;
-                    let new_framebuffers = get_framebuffers(&new_images, &render_pass);
-
-                    if window_resized {
-                        window_resized = false;
-
-                        viewport.dimensions = new_dimensions.into();
-                        command_buffers = build_command_buffers(
-                            &command_buffer_allocator,
-                            &queue,
-                            &new_framebuffers,
-                            &draw_pipeline,
-                            &compute_pipeline,
-                            &viewport,
-                            &image,
-                            &image_view
-                        );
-                    }
+                let now = Instant::now();
+                explorer.update(now - last_frame);
+                last_frame = now;
+                renderer.set_palette_shift(explorer.palette_shift());
+
+                if params_changed {
+                    params_changed = false;
+                    renderer.scene_changed();
                 }
 
-                let (image_i, suboptimal, acquire_future) =
-                    match swapchain::acquire_next_image(swapchain.clone(), None) {
-                        Ok(r) => r,
-                        Err(AcquireError::OutOfDate) => {
-                            recreate_swapchain = true;
-                            return;
-                        }
-                        Err(e) => panic!("failed to acquire next image: {}", e),
-                    };
-
-                if suboptimal {
-                    recreate_swapchain = true;
+                if let Err(e) = renderer.render(|extent| explorer.scene(extent)) {
+                    tracing::error!("{}", e);
+                    *control_flow = ControlFlow::ExitWithCode(1);
                 }
-
-                // Wait for the fence related to this image to finish (normally this would be the oldest fence)
-                if let Some(image_fence) = &fences[image_i as usize] {
-                    image_fence.wait(None).unwrap();
-                }
-
-                let previous_future = match fences[previous_fence_i as usize].clone() {
-                    None => {
-                        let mut now = sync::now(device.clone());
-                        now.cleanup_finished();
-
-                        now.boxed()
-                    }
-                    Some(fence) => fence.boxed()
-                };
-
-                let future = previous_future
-                    .join(acquire_future)
-                    .then_execute(queue.clone(), command_buffers[image_i as usize].clone())
-                    .unwrap()
-                    .then_swapchain_present(
-                        queue.clone(),
-                        SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_i),
-                    )
-                    .then_signal_fence_and_flush();
-
-                fences[image_i as usize] = match future {
-                    Ok(value) => Some(Arc::new(value)),
-                    Err(FlushError::OutOfDate) => {
-                        recreate_swapchain = true;
-                        None
-                    }
-                    Err(e) => {
-                        panic!("Failed to flush future: {}", e);
-                    }
-                };
-
-                previous_fence_i = image_i;
             }
             _ => {}
         }
     });
 }
-
@@ -0,0 +1,922 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
+
+use crate::error::Error;
+use crate::sdf::Material;
+
+/// Triangles in a leaf of the bounding volume hierarchy at most
+const LEAF_SIZE: usize = 4;
+
+/// Depth of the deepest leaves of the bounding volume hierarchy. The traversal in `mesh.glsl`
+/// keeps a deferred sibling per level and both children of the current node on a stack of
+/// `NODE_STACK_SIZE`, which has to stay one above this.
+const MAX_DEPTH: usize = 31;
+
+/// Colour of triangles without a material, the same as objects without a colour
+const DEFAULT_COLOR: [f32; 3] = [0.8, 0.8, 0.8];
+
+/// A Wavefront OBJ or glTF 2.0 file placed in a scene. Written as a table of `sdf.meshes`, e.g.
+/// `{ path = "monkey.glb", position = [0.0, 1.0, 0.0] }`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeshInstance {
+    /// `.obj` with the materials of its MTL library, or `.gltf` or `.glb`, relative to the
+    /// working directory
+    pub path: PathBuf,
+    /// Added to the vertices after scaling
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    /// Zero for flat shaded triangles
+    pub normal: [f32; 3],
+    /// Texture coordinates with v growing downwards, as in glTF
+    pub uv: [f32; 2],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Triangle {
+    /// Indices into `Mesh::vertices`
+    pub vertices: [u32; 3],
+    /// Index into `Mesh::materials`
+    pub material: u32,
+}
+
+/// Surface of the triangles that use it, like the colour and material of an object.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshMaterial {
+    /// In linear light, multiplied by the texture
+    pub color: [f32; 3],
+    pub material: Material,
+    /// Index into `Mesh::textures` of the base colour texture
+    pub texture: Option<usize>,
+}
+
+impl Default for MeshMaterial {
+    fn default() -> Self {
+        MeshMaterial {
+            color: DEFAULT_COLOR,
+            material: Material::Diffuse,
+            texture: None,
+        }
+    }
+}
+
+/// Base colour texture, sRGB RGBA texels packed little endian into `u32`s, rows from the top.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<u32>,
+}
+
+/// Triangles loaded from mesh files, with their materials and textures.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub triangles: Vec<Triangle>,
+    pub materials: Vec<MeshMaterial>,
+    pub textures: Vec<Texture>,
+}
+
+/// Meshes uploaded into the storage buffers the ray kernels intersect, see `mesh.glsl`. Buffers
+/// cannot be empty, a scene without triangles holds a single unused entry in each.
+#[derive(Clone)]
+pub struct MeshBuffers {
+    /// Position and u, then normal and v of each vertex
+    pub vertices: Subbuffer<[[f32; 4]]>,
+    /// Vertex indices and material of each triangle, in the order of the hierarchy leaves
+    pub triangles: Subbuffer<[[u32; 4]]>,
+    /// Bounding volume hierarchy, the root first. Each node is its lower corner and first child
+    /// or triangle, then its upper corner and triangle count, 0 for inner nodes whose second
+    /// child follows the first. The indices and counts are stored as their bits.
+    pub nodes: Subbuffer<[[f32; 4]]>,
+    /// Colour and kind, then the material parameter and the first texel, width and height of
+    /// the texture, as bits. A width of 0 has no texture.
+    pub materials: Subbuffer<[[f32; 4]]>,
+    /// Texels of all textures one after the other
+    pub texels: Subbuffer<[u32]>,
+    pub triangle_count: u32,
+}
+
+/// Node of the bounding volume hierarchy, a leaf if it has triangles.
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    lower: [f32; 3],
+    upper: [f32; 3],
+    /// First triangle of a leaf or first child of an inner node
+    first: u32,
+    count: u32,
+}
+
+impl Mesh {
+    /// Loads a Wavefront OBJ file with its materials, or a glTF 2.0 file with the transforms of
+    /// the nodes of its default scene, depending on the extension.
+    pub fn load(path: &Path) -> Result<Mesh, Error> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let mesh = match extension.as_deref() {
+            Some("obj") => load_obj(path),
+            Some("gltf" | "glb") => load_gltf(path),
+            _ => Err("unknown format, expected .obj, .gltf or .glb".to_owned()),
+        }
+        .map_err(|message| Error::Mesh(path.to_owned(), message))?;
+
+        tracing::info!(
+            "Loaded {} triangles and {} materials from {}",
+            mesh.triangles.len(),
+            mesh.materials.len(),
+            path.display(),
+        );
+        Ok(mesh)
+    }
+
+    /// Loads the files of `instances` into a single mesh, each scaled and moved into place.
+    pub fn load_instances(instances: &[MeshInstance]) -> Result<Mesh, Error> {
+        let mut mesh = Mesh::default();
+        for instance in instances {
+            let mut loaded = Mesh::load(&instance.path)?;
+            // Mirroring by a negative scale turns the normals inside out
+            let sign = instance.scale.signum();
+            for vertex in &mut loaded.vertices {
+                vertex.position = add(scale(vertex.position, instance.scale), instance.position);
+                vertex.normal = scale(vertex.normal, sign);
+            }
+            mesh.append(loaded);
+        }
+        Ok(mesh)
+    }
+
+    /// Adds the triangles, materials and textures of `other`.
+    pub fn append(&mut self, other: Mesh) {
+        let vertex_offset = self.vertices.len() as u32;
+        let material_offset = self.materials.len() as u32;
+        let texture_offset = self.textures.len();
+
+        self.vertices.extend(other.vertices);
+        self.triangles.extend(other.triangles.into_iter().map(|triangle| Triangle {
+            vertices: triangle.vertices.map(|v| v + vertex_offset),
+            material: triangle.material + material_offset,
+        }));
+        self.materials.extend(other.materials.into_iter().map(|material| MeshMaterial {
+            texture: material.texture.map(|t| t + texture_offset),
+            ..material
+        }));
+        self.textures.extend(other.textures);
+    }
+
+    /// Builds the bounding volume hierarchy and uploads it with the triangles into storage
+    /// buffers.
+    pub fn upload(&self, memory_allocator: &StandardMemoryAllocator) -> Result<MeshBuffers, Error> {
+        let (nodes, order) = self.build_hierarchy(MAX_DEPTH);
+
+        let vertices = self.vertices.iter().flat_map(|vertex| {
+            let [x, y, z] = vertex.position;
+            let [nx, ny, nz] = vertex.normal;
+            let [u, v] = vertex.uv;
+            [[x, y, z, u], [nx, ny, nz, v]]
+        });
+        let triangles = order.iter().map(|&i| {
+            let triangle = self.triangles[i as usize];
+            let [a, b, c] = triangle.vertices;
+            [a, b, c, triangle.material]
+        });
+        let nodes = nodes.iter().flat_map(|node| {
+            let [lx, ly, lz] = node.lower;
+            let [ux, uy, uz] = node.upper;
+            [[lx, ly, lz, f32::from_bits(node.first)], [ux, uy, uz, f32::from_bits(node.count)]]
+        });
+
+        let mut first_texels = Vec::with_capacity(self.textures.len());
+        let mut texel_count = 0;
+        for texture in &self.textures {
+            first_texels.push(texel_count);
+            texel_count += texture.texels.len() as u32;
+        }
+        let materials = self.materials.iter().flat_map(|material| {
+            let [r, g, b] = material.color;
+            let [kind, parameter] = material.material.encode();
+            let (first, width, height) = match material.texture {
+                Some(t) => (first_texels[t], self.textures[t].width, self.textures[t].height),
+                None => (0, 0, 0),
+            };
+            [[r, g, b, kind], [parameter, f32::from_bits(first), f32::from_bits(width), f32::from_bits(height)]]
+        });
+        let texels = self.textures.iter().flat_map(|texture| texture.texels.iter().copied());
+
+        Ok(MeshBuffers {
+            vertices: storage_buffer(memory_allocator, vertices.collect(), [0.0; 4])?,
+            triangles: storage_buffer(memory_allocator, triangles.collect(), [0; 4])?,
+            nodes: storage_buffer(memory_allocator, nodes.collect(), [0.0; 4])?,
+            materials: storage_buffer(memory_allocator, materials.collect(), [0.0; 4])?,
+            texels: storage_buffer(memory_allocator, texels.collect(), 0)?,
+            triangle_count: self.triangles.len() as u32,
+        })
+    }
+
+    /// Splits the triangles in half along the longest axis of their centres until they fit
+    /// into leaves, or the leaves are `max_depth` levels below the root. Returns the nodes with
+    /// the children of each inner node next to each other, and the triangles in the order the
+    /// leaves refer to them.
+    fn build_hierarchy(&self, max_depth: usize) -> (Vec<BvhNode>, Vec<u32>) {
+        let bounds: Vec<([f32; 3], [f32; 3])> = self
+            .triangles
+            .iter()
+            .map(|triangle| {
+                let [a, b, c] = triangle.vertices.map(|v| self.vertices[v as usize].position);
+                (min(min(a, b), c), max(max(a, b), c))
+            })
+            .collect();
+        let centers: Vec<[f32; 3]> = bounds.iter().map(|&(lower, upper)| scale(add(lower, upper), 0.5)).collect();
+
+        let mut order: Vec<u32> = (0..self.triangles.len() as u32).collect();
+        let mut nodes = vec![BvhNode { lower: [0.0; 3], upper: [0.0; 3], first: 0, count: 0 }];
+        let mut pending = vec![(0, 0, order.len(), 0)];
+        while let Some((node, start, end, depth)) = pending.pop() {
+            let triangles = &mut order[start..end];
+            let (lower, upper) = triangles.iter().fold(
+                ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]),
+                |(lower, upper), &t| (min(lower, bounds[t as usize].0), max(upper, bounds[t as usize].1)),
+            );
+
+            // Halving only reaches the limit with billions of triangles
+            if triangles.len() <= LEAF_SIZE || depth >= max_depth {
+                nodes[node] = BvhNode { lower, upper, first: start as u32, count: triangles.len() as u32 };
+                continue;
+            }
+
+            let (center_lower, center_upper) = triangles.iter().fold(
+                ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]),
+                |(lower, upper), &t| (min(lower, centers[t as usize]), max(upper, centers[t as usize])),
+            );
+            let extent = sub(center_upper, center_lower);
+            let axis = (0..3).max_by(|&a, &b| extent[a].total_cmp(&extent[b])).unwrap();
+            let half = triangles.len() / 2;
+            triangles.select_nth_unstable_by(half, |&a, &b| {
+                centers[a as usize][axis].total_cmp(&centers[b as usize][axis])
+            });
+
+            let children = nodes.len();
+            nodes.extend([nodes[node]; 2]);
+            nodes[node] = BvhNode { lower, upper, first: children as u32, count: 0 };
+            pending.push((children, start, start + half, depth + 1));
+            pending.push((children + 1, start + half, end, depth + 1));
+        }
+        (nodes, order)
+    }
+}
+
+fn storage_buffer<T: BufferContents>(
+    memory_allocator: &StandardMemoryAllocator,
+    mut data: Vec<T>,
+    unused: T,
+) -> Result<Subbuffer<[T]>, Error> {
+    if data.is_empty() {
+        data.push(unused);
+    }
+
+    Ok(Buffer::from_iter(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            usage: MemoryUsage::Upload,
+            ..Default::default()
+        },
+        data,
+    )?)
+}
+
+/// Triangulated models of an OBJ file, each vertex with its own position, normal and texture
+/// coordinates, and the materials of its MTL library.
+fn load_obj(path: &Path) -> Result<Mesh, String> {
+    let (models, materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(|e| e.to_string())?;
+    // Without its library the model is still worth showing
+    let materials = materials.unwrap_or_else(|e| {
+        tracing::warn!("Failed to load the materials of {}: {}", path.display(), e);
+        Vec::new()
+    });
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut mesh = Mesh::default();
+    let mut textures = HashMap::new();
+    for material in &materials {
+        let texture = material
+            .diffuse_texture
+            .as_ref()
+            .and_then(|name| load_texture_file(&mut mesh, &mut textures, &directory.join(name)));
+        mesh.materials.push(MeshMaterial { texture, ..obj_material(material) });
+    }
+    // Triangles without a material, or with one missing from the library
+    let default_material = mesh.materials.len() as u32;
+    mesh.materials.push(MeshMaterial::default());
+
+    for model in models {
+        let obj = model.mesh;
+        let material = obj
+            .material_id
+            .filter(|&id| id < materials.len())
+            .map_or(default_material, |id| id as u32);
+        let offset = mesh.vertices.len() as u32;
+
+        for i in 0..obj.positions.len() / 3 {
+            let vec3 = |data: &[f32]| data.get(3 * i..3 * i + 3).map_or([0.0; 3], |v| [v[0], v[1], v[2]]);
+            // OBJ texture coordinates grow upwards
+            let uv = obj.texcoords.get(2 * i..2 * i + 2).map_or([0.0; 2], |uv| [uv[0], 1.0 - uv[1]]);
+            mesh.vertices.push(Vertex {
+                position: vec3(&obj.positions),
+                normal: vec3(&obj.normals),
+                uv,
+            });
+        }
+        for indices in obj.indices.chunks_exact(3) {
+            mesh.triangles.push(Triangle {
+                vertices: [indices[0] + offset, indices[1] + offset, indices[2] + offset],
+                material,
+            });
+        }
+    }
+    Ok(mesh)
+}
+
+/// The colour and material closest to an MTL material. Blender writes emission as `Ke`,
+/// metallic and roughness as `Pm` and `Pr` and marks transmissive materials by their
+/// illumination model.
+fn obj_material(material: &tobj::Material) -> MeshMaterial {
+    let parameter = |key: &str| -> Vec<f32> {
+        material
+            .unknown_param
+            .get(key)
+            .map(|value| value.split_whitespace().filter_map(|v| v.parse().ok()).collect())
+            .unwrap_or_default()
+    };
+    let color = material.diffuse.unwrap_or(DEFAULT_COLOR);
+
+    let emission = match parameter("Ke")[..] {
+        [r, g, b, ..] => [r, g, b],
+        [v] => [v; 3],
+        _ => [0.0; 3],
+    };
+    // Blender writes the shininess as (1 - roughness)² * 1000
+    let roughness = parameter("Pr")
+        .first()
+        .copied()
+        .or(material.shininess.map(|s| 1.0 - (s / 1000.0).clamp(0.0, 1.0).sqrt()))
+        .unwrap_or(1.0);
+    let illumination = material.illumination_model.unwrap_or(2);
+    let metallic = parameter("Pm").first().map_or(illumination == 3, |&m| m >= 0.5);
+    let transparent = material.dissolve.is_some_and(|d| d < 1.0) || matches!(illumination, 4 | 6 | 7 | 9);
+
+    if let Some(emissive) = emissive(emission, 1.0) {
+        return emissive;
+    }
+    let material = if transparent {
+        Material::Dielectric { ior: material.optical_density.filter(|&n| n >= 1.0).unwrap_or(1.5) }
+    } else if metallic {
+        Material::Metal { roughness }
+    } else {
+        Material::Diffuse
+    };
+    MeshMaterial { color, material, texture: None }
+}
+
+/// Light emitting material of `emission` times `strength`, if it glows at all.
+fn emissive(emission: [f32; 3], strength: f32) -> Option<MeshMaterial> {
+    let brightest = emission.into_iter().fold(0.0, f32::max) * strength;
+    (brightest > 0.0).then(|| MeshMaterial {
+        color: scale(emission, strength / brightest),
+        material: Material::Emissive { strength: brightest },
+        texture: None,
+    })
+}
+
+/// Triangles of the meshes of the default scene, or the first one, with the node transforms
+/// applied.
+fn load_gltf(path: &Path) -> Result<Mesh, String> {
+    let gltf::Gltf { document, mut blob } = gltf::Gltf::open(path).map_err(|e| e.to_string())?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let buffers = document
+        .buffers()
+        .map(|buffer| {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Bin => blob.take().ok_or("the binary chunk is missing")?,
+                gltf::buffer::Source::Uri(uri) => read_uri(directory, uri)?,
+            };
+            if data.len() < buffer.length() {
+                return Err(format!("buffer {} is shorter than declared", buffer.index()));
+            }
+            Ok(data)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut mesh = Mesh::default();
+    let mut textures = HashMap::new();
+    for material in document.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let texture = pbr.base_color_texture().and_then(|info| {
+            let image = info.texture().source();
+            if let Some(&index) = textures.get(&image.index()) {
+                return Some(index);
+            }
+            let texture = read_image(&buffers, directory, &image).and_then(|data| decode_texture(&data));
+            let index = store_texture(&mut mesh, texture, &format!("image {}", image.index()))?;
+            textures.insert(image.index(), index);
+            Some(index)
+        });
+
+        let transmission = material.transmission().map_or(0.0, |t| t.transmission_factor());
+        let emission = material.emissive_factor();
+        let strength = material.emissive_strength().unwrap_or(1.0);
+        let mesh_material = match emissive(emission, strength) {
+            Some(emissive) => emissive,
+            None if transmission >= 0.5 => MeshMaterial {
+                color: [r, g, b],
+                material: Material::Dielectric { ior: material.ior().unwrap_or(1.5) },
+                texture,
+            },
+            None if pbr.metallic_factor() >= 0.5 => MeshMaterial {
+                color: [r, g, b],
+                material: Material::Metal { roughness: pbr.roughness_factor() },
+                texture,
+            },
+            None => MeshMaterial { color: [r, g, b], material: Material::Diffuse, texture },
+        };
+        mesh.materials.push(mesh_material);
+    }
+    // The default material of glTF would be a rough metal, grey shows the shape better
+    let default_material = mesh.materials.len() as u32;
+    mesh.materials.push(MeshMaterial::default());
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or("the file has no scene")?;
+    let mut pending: Vec<_> = scene.nodes().map(|node| (node, IDENTITY)).collect();
+    while let Some((node, parent)) = pending.pop() {
+        let transform = multiply(parent, node.transform().matrix());
+        pending.extend(node.children().map(|child| (child, transform)));
+
+        let Some(node_mesh) = node.mesh() else {
+            continue;
+        };
+        for primitive in node_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                tracing::warn!("Skipping {:?} of mesh {} in {}", primitive.mode(), node_mesh.index(), path.display());
+                continue;
+            }
+            let material = primitive.material().index().map_or(default_material, |i| i as u32);
+            append_primitive(&mut mesh, &primitive, &buffers, transform, material)?;
+        }
+    }
+    Ok(mesh)
+}
+
+/// Adds the vertices and triangles of a primitive, transformed into the coordinates of the scene.
+fn append_primitive(
+    mesh: &mut Mesh,
+    primitive: &gltf::Primitive,
+    buffers: &[Vec<u8>],
+    transform: [[f32; 4]; 4],
+    material: u32,
+) -> Result<(), String> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+    let positions: Vec<[f32; 3]> = reader.read_positions().ok_or("a primitive has no positions")?.collect();
+    let normals: Vec<[f32; 3]> = reader.read_normals().map_or(Vec::new(), |normals| normals.collect());
+    let uvs: Vec<[f32; 2]> = reader.read_tex_coords(0).map_or(Vec::new(), |uvs| uvs.into_f32().collect());
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+        return Err(format!("vertex index {} is out of range", index));
+    }
+
+    // Normals transform by the inverse transpose, which is the cofactor matrix up to its scale
+    let [x, y, z] = [0, 1, 2].map(|i| [transform[i][0], transform[i][1], transform[i][2]]);
+    let sign = dot(x, cross(y, z)).signum();
+    let cofactors = [cross(y, z), cross(z, x), cross(x, y)];
+
+    let offset = mesh.vertices.len() as u32;
+    for (i, &position) in positions.iter().enumerate() {
+        let normal = normals.get(i).map_or([0.0; 3], |&[nx, ny, nz]| {
+            let n = add(add(scale(cofactors[0], nx), scale(cofactors[1], ny)), scale(cofactors[2], nz));
+            let length = dot(n, n).sqrt();
+            if length > 0.0 { scale(n, sign / length) } else { [0.0; 3] }
+        });
+        let [px, py, pz] = position;
+        let transformed = [0, 1, 2].map(|r| {
+            transform[0][r] * px + transform[1][r] * py + transform[2][r] * pz + transform[3][r]
+        });
+        mesh.vertices.push(Vertex {
+            position: transformed,
+            normal,
+            uv: uvs.get(i).copied().unwrap_or([0.0; 2]),
+        });
+    }
+    for triangle in indices.chunks_exact(3) {
+        mesh.triangles.push(Triangle {
+            vertices: [triangle[0] + offset, triangle[1] + offset, triangle[2] + offset],
+            material,
+        });
+    }
+    Ok(())
+}
+
+/// Encoded bytes of an image stored in a buffer view or referenced by a URI.
+fn read_image(buffers: &[Vec<u8>], directory: &Path, image: &gltf::Image) -> Result<Vec<u8>, String> {
+    match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let buffer = &buffers[view.buffer().index()];
+            buffer
+                .get(view.offset()..view.offset() + view.length())
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| format!("the view of image {} is out of range", image.index()))
+        }
+        gltf::image::Source::Uri { uri, .. } => read_uri(directory, uri),
+    }
+}
+
+/// Data of a base64 `data:` URI, or the contents of a file relative to `directory`.
+fn read_uri(directory: &Path, uri: &str) -> Result<Vec<u8>, String> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data.split_once(";base64,").ok_or("data URIs have to be base64 encoded")?;
+        return base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| format!("invalid data URI: {}", e));
+    }
+
+    let path = directory.join(percent_decode(uri));
+    fs::read(&path).map_err(|e| format!("failed to read {}: {}", path.display(), e))
+}
+
+/// Replaces `%XX` escapes, Blender writes spaces in file names as `%20`.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn decode_texture(data: &[u8]) -> Result<Texture, String> {
+    let image = image::load_from_memory(data).map_err(|e| e.to_string())?.into_rgba8();
+    Ok(Texture {
+        width: image.width(),
+        height: image.height(),
+        texels: image.pixels().map(|pixel| u32::from_le_bytes(pixel.0)).collect(),
+    })
+}
+
+/// Loads a texture file once, even if several materials use it.
+fn load_texture_file(mesh: &mut Mesh, textures: &mut HashMap<PathBuf, usize>, path: &Path) -> Option<usize> {
+    if let Some(&index) = textures.get(path) {
+        return Some(index);
+    }
+    let texture = fs::read(path).map_err(|e| e.to_string()).and_then(|data| decode_texture(&data));
+    let index = store_texture(mesh, texture, &path.display().to_string())?;
+    textures.insert(path.to_owned(), index);
+    Some(index)
+}
+
+/// Adds a loaded texture to the mesh. A missing texture leaves its materials untextured instead of
+/// failing the whole mesh.
+fn store_texture(mesh: &mut Mesh, texture: Result<Texture, String>, name: &str) -> Option<usize> {
+    match texture {
+        Ok(texture) => {
+            mesh.textures.push(texture);
+            Some(mesh.textures.len() - 1)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to load the texture {}: {}", name, e);
+            None
+        }
+    }
+}
+
+const IDENTITY: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Product of column major matrices, `a` applied last.
+fn multiply(a: [[f32; 4]; 4], b: [[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    b.map(|column| [0, 1, 2, 3].map(|r| (0..4).map(|k| a[k][r] * column[k]).sum()))
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(v: [f32; 3], s: f32) -> [f32; 3] {
+    [v[0] * s, v[1] * s, v[2] * s]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn min(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2])]
+}
+
+fn max(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0].max(b[0]), a[1].max(b[1]), a[2].max(b[2])]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` into a directory of their own and returns it.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("sel-mesh-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for (file, contents) in files {
+            fs::write(directory.join(file), contents).unwrap();
+        }
+        directory
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn loads_obj_with_materials() {
+        let obj = "\
+mtllib scene.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+usemtl glass
+f 1/1/1 2/2/1 3/3/1 4/4/1
+usemtl lamp
+f 1/1/1 3/3/1 4/4/1
+usemtl missing
+f 1/1/1 2/2/1 3/3/1
+";
+        let mtl = "\
+newmtl glass
+Kd 0.9 0.9 1.0
+Ni 1.45
+illum 7
+newmtl lamp
+Kd 1 1 1
+Ke 2 1 0
+";
+        let directory = write_files("obj", &[("scene.obj", obj), ("scene.mtl", mtl)]);
+        let mesh = Mesh::load(&directory.join("scene.obj")).unwrap();
+
+        assert_eq!(mesh.materials, [
+            MeshMaterial { color: [0.9, 0.9, 1.0], material: Material::Dielectric { ior: 1.45 }, texture: None },
+            MeshMaterial { color: [1.0, 0.5, 0.0], material: Material::Emissive { strength: 2.0 }, texture: None },
+            MeshMaterial::default(),
+        ]);
+        // The quad is split in two
+        let materials: Vec<u32> = mesh.triangles.iter().map(|t| t.material).collect();
+        assert_eq!(materials, [0, 0, 1, 2]);
+
+        // Texture coordinates are flipped to grow downwards
+        let corner = mesh.vertices.iter().find(|v| v.position == [1.0, 1.0, 0.0]).unwrap();
+        assert_eq!(corner.uv, [1.0, 0.0]);
+        assert_eq!(corner.normal, [0.0, 0.0, 1.0]);
+        for triangle in &mesh.triangles {
+            assert!(triangle.vertices.iter().all(|&v| (v as usize) < mesh.vertices.len()));
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn places_instances() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\n";
+        let directory = write_files("instances", &[("triangle.obj", obj)]);
+        let path = directory.join("triangle.obj");
+        let mesh = Mesh::load_instances(&[
+            MeshInstance { path: path.clone(), position: [0.0; 3], scale: 1.0 },
+            MeshInstance { path, position: [0.0, 0.0, 5.0], scale: -2.0 },
+        ]).unwrap();
+
+        assert_eq!(mesh.triangles.len(), 2);
+        assert_eq!(mesh.triangles[1].vertices, [3, 4, 5]);
+        assert_eq!(mesh.triangles[1].material, 1);
+        assert_eq!(mesh.vertices[4].position, [-2.0, 0.0, 5.0]);
+        // Mirrored
+        assert_eq!(mesh.vertices[4].normal, [0.0, 0.0, -1.0]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// Loads a glTF file of a single triangle with a metal material, its mesh node placed by
+    /// the properties in `node` under a parent moved by (1, 2, 3).
+    fn load_triangle_gltf(name: &str, node: &str) -> Mesh {
+        let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let normals = [[0.6f32, 0.8, 0.0]; 3];
+        let bytes: Vec<u8> = positions
+            .iter()
+            .chain(&normals)
+            .flatten()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        let gltf = format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scene": 0,
+            "scenes": [{{ "nodes": [0] }}],
+            "nodes": [
+                {{ "translation": [1, 2, 3], "children": [1] }},
+                {{ "mesh": 0, {} }}
+            ],
+            "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0, "NORMAL": 1 }}, "material": 0 }}] }}],
+            "materials": [{{ "pbrMetallicRoughness": {{ "baseColorFactor": [0.5, 0.25, 1, 1], "metallicFactor": 1, "roughnessFactor": 0.25 }} }}],
+            "buffers": [{{ "byteLength": 72, "uri": "data:application/octet-stream;base64,{}" }}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 36 }}
+            ],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] }},
+                {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" }}
+            ]
+        }}"#, node, base64::engine::general_purpose::STANDARD.encode(&bytes));
+        let directory = write_files(name, &[("triangle.gltf", &gltf)]);
+        let mesh = Mesh::load(&directory.join("triangle.gltf")).unwrap();
+        fs::remove_dir_all(directory).unwrap();
+        mesh
+    }
+
+    #[test]
+    fn loads_gltf_with_data_uri() {
+        let mesh = load_triangle_gltf("gltf", r#""scale": [2, 1, 1]"#);
+
+        assert_eq!(mesh.materials, [
+            MeshMaterial { color: [0.5, 0.25, 1.0], material: Material::Metal { roughness: 0.25 }, texture: None },
+            MeshMaterial::default(),
+        ]);
+        assert_eq!(mesh.triangles, [Triangle { vertices: [0, 1, 2], material: 0 }]);
+        let positions: Vec<[f32; 3]> = mesh.vertices.iter().map(|v| v.position).collect();
+        assert_eq!(positions, [[1.0, 2.0, 3.0], [3.0, 2.0, 3.0], [1.0, 3.0, 3.0]]);
+        // Stretching along x tilts the normal away from it
+        let length = (0.3f32 * 0.3 + 0.8 * 0.8).sqrt();
+        assert_close(mesh.vertices[0].normal, [0.3 / length, 0.8 / length, 0.0]);
+    }
+
+    #[test]
+    fn transforms_gltf_normals() {
+        // Mirrored along x
+        let mesh = load_triangle_gltf("gltf-mirror", r#""scale": [-1, 1, 1]"#);
+        assert_eq!(mesh.vertices[1].position, [0.0, 2.0, 3.0]);
+        assert_close(mesh.vertices[0].normal, [-0.6, 0.8, 0.0]);
+
+        // A quarter turn around z, as a column major matrix
+        let mesh = load_triangle_gltf("gltf-matrix", r#""matrix": [0, 1, 0, 0, -1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1]"#);
+        assert_close(mesh.vertices[1].position, [1.0, 3.0, 3.0]);
+        assert_close(mesh.vertices[0].normal, [-0.8, 0.6, 0.0]);
+    }
+
+    #[test]
+    fn reads_data_uris() {
+        assert_eq!(read_uri(Path::new(""), "data:application/octet-stream;base64,AQID").unwrap(), [1, 2, 3]);
+        assert!(read_uri(Path::new(""), "data:text/plain,123").is_err());
+        assert!(read_uri(Path::new(""), "data:application/octet-stream;base64,!!").is_err());
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("my%20texture.png"), "my texture.png");
+        assert_eq!(percent_decode("caf%C3%A9.png"), "café.png");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+        assert_eq!(percent_decode("plain.png"), "plain.png");
+    }
+
+    #[test]
+    fn multiplies_column_major_matrices() {
+        let translation = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [1.0, 2.0, 3.0, 1.0]];
+        let scaling = [[2.0, 0.0, 0.0, 0.0], [0.0, 3.0, 0.0, 0.0], [0.0, 0.0, 4.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+        assert_eq!(multiply(IDENTITY, translation), translation);
+        assert_eq!(multiply(scaling, IDENTITY), scaling);
+        // Scaling after translating scales the translation too
+        assert_eq!(multiply(scaling, translation)[3], [2.0, 6.0, 12.0, 1.0]);
+        assert_eq!(multiply(translation, scaling)[3], [1.0, 2.0, 3.0, 1.0]);
+    }
+
+    /// Triangles on a jittered grid of `n` x `n` cells.
+    fn grid(n: u32) -> Mesh {
+        let mut mesh = Mesh::default();
+        for i in 0..n * n {
+            let [x, y] = [(i % n) as f32, (i / n) as f32];
+            let jitter = (i as f32 * 0.618).fract();
+            let offset = mesh.vertices.len() as u32;
+            for position in [[x, y, jitter], [x + 1.0, y, 0.0], [x, y + 1.0, -jitter]] {
+                mesh.vertices.push(Vertex { position, ..Vertex::default() });
+            }
+            mesh.triangles.push(Triangle { vertices: [offset, offset + 1, offset + 2], material: 0 });
+        }
+        mesh
+    }
+
+    /// Checks that every node bounds its triangles and returns the depth of the deepest leaf.
+    fn check_node(mesh: &Mesh, nodes: &[BvhNode], order: &[u32], node: usize, depth: usize, seen: &mut [bool]) -> usize {
+        let BvhNode { lower, upper, first, count } = nodes[node];
+        let inside = |p: [f32; 3]| (0..3).all(|i| lower[i] <= p[i] && p[i] <= upper[i]);
+        if count > 0 {
+            for &triangle in &order[first as usize..(first + count) as usize] {
+                assert!(!seen[triangle as usize], "triangle {} is in two leaves", triangle);
+                seen[triangle as usize] = true;
+                for v in mesh.triangles[triangle as usize].vertices {
+                    assert!(inside(mesh.vertices[v as usize].position));
+                }
+            }
+            return depth;
+        }
+        let children = [first as usize, first as usize + 1];
+        for child in children {
+            assert!(inside(nodes[child].lower) && inside(nodes[child].upper));
+        }
+        children.map(|child| check_node(mesh, nodes, order, child, depth + 1, seen)).into_iter().max().unwrap()
+    }
+
+    #[test]
+    fn hierarchy_bounds_every_triangle() {
+        let mesh = grid(40);
+        let (nodes, order) = mesh.build_hierarchy(MAX_DEPTH);
+        let mut seen = vec![false; mesh.triangles.len()];
+        let depth = check_node(&mesh, &nodes, &order, 0, 0, &mut seen);
+
+        assert!(seen.iter().all(|&seen| seen));
+        assert!(nodes.iter().all(|node| node.count as usize <= LEAF_SIZE));
+        // 1600 triangles halve into leaves of 4 or fewer after 9 levels
+        assert_eq!(depth, 9);
+        let positions = mesh.vertices.iter().map(|v| v.position);
+        assert_eq!(nodes[0].lower, positions.clone().fold([f32::INFINITY; 3], min));
+        assert_eq!(nodes[0].upper, positions.fold([f32::NEG_INFINITY; 3], max));
+    }
+
+    #[test]
+    fn hierarchy_depth_is_capped() {
+        let mesh = grid(10);
+        let (nodes, order) = mesh.build_hierarchy(2);
+        let mut seen = vec![false; mesh.triangles.len()];
+        assert_eq!(check_node(&mesh, &nodes, &order, 0, 0, &mut seen), 2);
+        assert_eq!(nodes.len(), 7);
+        assert!(seen.iter().all(|&seen| seen));
+        assert_eq!(nodes.iter().map(|node| node.count).sum::<u32>(), 100);
+    }
+
+    #[test]
+    fn hierarchy_of_nothing_is_an_empty_leaf() {
+        let (nodes, order) = Mesh::default().build_hierarchy(MAX_DEPTH);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].count, 0);
+        assert!(order.is_empty());
+    }
+}
@@ -0,0 +1,518 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
+
+use crate::error::Error;
+
+/// Number of entries gradients are sampled into.
+const GRADIENT_SIZE: usize = 256;
+
+/// A color lookup table for the escape-time output, uploaded to the compute shaders as a storage buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    /// Built-in name or the path of the file it was loaded from, `Palette::load` takes it back
+    pub name: String,
+    /// Linear interpolated RGBA entries, the palette wraps around from the last to the first entry
+    pub colors: Vec<[f32; 4]>,
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(PathBuf, io::Error),
+    /// The file extension is not one of `map`, `gpl` or `ggr`
+    UnknownFormat(String),
+    Parse { line: usize, message: String },
+    Empty,
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            PaletteError::UnknownFormat(name) => write!(f, "unknown palette format or built-in palette: {}", name),
+            PaletteError::Parse { line, message } => write!(f, "invalid palette on line {}: {}", line, message),
+            PaletteError::Empty => write!(f, "palette contains no colors"),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PaletteError::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Palette {
+    /// Names of the palettes compiled into the binary, in the order they are cycled through.
+    pub const BUILT_IN: [&'static str; 4] = ["classic", "fire", "ocean", "grayscale"];
+
+    pub fn built_in(name: &str) -> Option<Palette> {
+        let stops: &[(f32, [u8; 3])] = match name {
+            "classic" => &[
+                (0.0, [0, 7, 100]),
+                (0.16, [32, 107, 203]),
+                (0.42, [237, 255, 255]),
+                (0.6425, [255, 170, 0]),
+                (0.8575, [0, 2, 0]),
+            ],
+            "fire" => &[
+                (0.0, [0, 0, 0]),
+                (0.3, [128, 0, 0]),
+                (0.55, [255, 96, 0]),
+                (0.8, [255, 230, 64]),
+                (0.9, [255, 255, 255]),
+            ],
+            "ocean" => &[
+                (0.0, [0, 16, 32]),
+                (0.35, [0, 96, 128]),
+                (0.6, [64, 192, 192]),
+                (0.8, [224, 255, 240]),
+            ],
+            "grayscale" => &[
+                (0.0, [0, 0, 0]),
+                (0.5, [255, 255, 255]),
+            ],
+            _ => return None,
+        };
+
+        Some(Palette::from_stops(name, stops))
+    }
+
+    /// Resolves `name` as a built-in palette first and as a palette file otherwise.
+    pub fn load(name: &str) -> Result<Palette, PaletteError> {
+        match Palette::built_in(name) {
+            Some(palette) => Ok(palette),
+            None => Palette::from_file(Path::new(name)),
+        }
+    }
+
+    /// Loads a Fractint `.map`, GIMP palette `.gpl` or GIMP gradient `.ggr` file.
+    pub fn from_file(path: &Path) -> Result<Palette, PaletteError> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let name = path.display().to_string();
+        let read = || fs::read_to_string(path).map_err(|e| PaletteError::Io(path.to_owned(), e));
+
+        let colors = match extension.as_deref() {
+            Some("map") => parse_map(&read()?)?,
+            Some("gpl") => parse_gpl(&read()?)?,
+            Some("ggr") => parse_ggr(&read()?)?,
+            _ => return Err(PaletteError::UnknownFormat(path.display().to_string())),
+        };
+
+        if colors.is_empty() {
+            return Err(PaletteError::Empty);
+        }
+
+        Ok(Palette { name, colors })
+    }
+
+    /// Samples a gradient through the given `(position, color)` stops, wrapping back to the first stop.
+    fn from_stops(name: &str, stops: &[(f32, [u8; 3])]) -> Palette {
+        let colors = (0..GRADIENT_SIZE)
+            .map(|i| {
+                let t = i as f32 / GRADIENT_SIZE as f32;
+                let next = stops.iter().position(|(p, _)| *p > t).unwrap_or(stops.len());
+                let (p0, c0) = stops[(next + stops.len() - 1) % stops.len()];
+                let (p1, c1) = stops[next % stops.len()];
+
+                // The last segment wraps around to the first stop
+                let p0 = if p0 > t { p0 - 1.0 } else { p0 };
+                let p1 = if p1 <= t { p1 + 1.0 } else { p1 };
+                let f = if p1 > p0 { (t - p0) / (p1 - p0) } else { 0.0 };
+
+                let c0 = rgb8_to_rgba(c0);
+                let c1 = rgb8_to_rgba(c1);
+                [
+                    c0[0] + (c1[0] - c0[0]) * f,
+                    c0[1] + (c1[1] - c0[1]) * f,
+                    c0[2] + (c1[2] - c0[2]) * f,
+                    1.0,
+                ]
+            })
+            .collect();
+
+        Palette {
+            name: name.to_owned(),
+            colors,
+        }
+    }
+
+    pub fn upload(&self, memory_allocator: &StandardMemoryAllocator) -> Result<Subbuffer<[[f32; 4]]>, Error> {
+        Ok(Buffer::from_iter(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Upload,
+                ..Default::default()
+            },
+            self.colors.iter().copied(),
+        )?)
+    }
+}
+
+fn rgb8_to_rgba(rgb: [u8; 3]) -> [f32; 4] {
+    [rgb[0] as f32 / 255.0, rgb[1] as f32 / 255.0, rgb[2] as f32 / 255.0, 1.0]
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> PaletteError {
+    PaletteError::Parse { line: line + 1, message: message.into() }
+}
+
+/// Parses the leading `r g b` triplet of a line, anything after it is a comment or color name.
+fn parse_rgb8(line: usize, text: &str) -> Result<[u8; 3], PaletteError> {
+    let mut values = text.split_whitespace().map(|v| {
+        v.parse::<u8>()
+            .map_err(|_| parse_error(line, format!("expected a color component between 0 and 255, got '{}'", v)))
+    });
+
+    let mut next = || values.next().unwrap_or_else(|| Err(parse_error(line, "expected three color components")));
+    Ok([next()?, next()?, next()?])
+}
+
+/// Fractint maps list one `r g b` color per line.
+fn parse_map(text: &str) -> Result<Vec<[f32; 4]>, PaletteError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| parse_rgb8(i, l).map(rgb8_to_rgba))
+        .collect()
+}
+
+/// GIMP palettes have a `GIMP Palette` header and optional `Name:` / `Columns:` fields before the colors.
+fn parse_gpl(text: &str) -> Result<Vec<[f32; 4]>, PaletteError> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == "GIMP Palette" => {}
+        _ => return Err(parse_error(0, "missing 'GIMP Palette' header")),
+    }
+
+    lines
+        .filter(|(_, l)| {
+            let l = l.trim();
+            !(l.is_empty() || l.starts_with('#') || l.starts_with("Name:") || l.starts_with("Columns:"))
+        })
+        .map(|(i, l)| parse_rgb8(i, l).map(rgb8_to_rgba))
+        .collect()
+}
+
+struct GradientSegment {
+    left: f32,
+    middle: f32,
+    right: f32,
+    left_color: [f32; 4],
+    right_color: [f32; 4],
+    blend: u32,
+    coloring: u32,
+}
+
+/// GIMP gradients consist of segments with a blending function and an RGB or HSV coloring mode.
+fn parse_ggr(text: &str) -> Result<Vec<[f32; 4]>, PaletteError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty());
+
+    match lines.next() {
+        Some((_, header)) if header.trim() == "GIMP Gradient" => {}
+        _ => return Err(parse_error(0, "missing 'GIMP Gradient' header")),
+    }
+
+    let (count_line, count) = match lines.next() {
+        Some((_, l)) if l.starts_with("Name:") => lines.next(),
+        other => other,
+    }.ok_or_else(|| parse_error(1, "missing segment count"))?;
+    let count: usize = count
+        .trim()
+        .parse()
+        .map_err(|_| parse_error(count_line, "invalid segment count"))?;
+
+    let segments = lines
+        .take(count)
+        .map(|(i, l)| {
+            let values = l
+                .split_whitespace()
+                .map(|v| v.parse::<f32>().map_err(|_| parse_error(i, format!("invalid number '{}'", v))))
+                .collect::<Result<Vec<_>, _>>()?;
+            if values.len() < 11 {
+                return Err(parse_error(i, "expected at least 11 values per segment"));
+            }
+            Ok(GradientSegment {
+                left: values[0],
+                middle: values[1],
+                right: values[2],
+                left_color: [values[3], values[4], values[5], values[6]],
+                right_color: [values[7], values[8], values[9], values[10]],
+                blend: values.get(11).copied().unwrap_or(0.0) as u32,
+                coloring: values.get(12).copied().unwrap_or(0.0) as u32,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if segments.len() != count {
+        return Err(parse_error(count_line, format!("expected {} segments, found {}", count, segments.len())));
+    }
+    if segments.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok((0..GRADIENT_SIZE)
+        .map(|i| {
+            let t = (i as f32 + 0.5) / GRADIENT_SIZE as f32;
+            let segment = segments
+                .iter()
+                .find(|s| t <= s.right)
+                .unwrap_or(segments.last().unwrap());
+            segment.sample(t)
+        })
+        .collect())
+}
+
+impl GradientSegment {
+    fn sample(&self, t: f32) -> [f32; 4] {
+        let length = self.right - self.left;
+        let (pos, middle) = if length > f32::EPSILON {
+            ((t - self.left) / length, (self.middle - self.left) / length)
+        } else {
+            (0.5, 0.5)
+        };
+
+        let factor = match self.blend {
+            // Curved
+            1 => pos.max(f32::EPSILON).powf(0.5f32.ln() / middle.max(f32::EPSILON).ln()),
+            // Sine
+            2 => ((-PI / 2.0 + PI * linear_factor(middle, pos)).sin() + 1.0) / 2.0,
+            // Sphere increasing
+            3 => {
+                let f = linear_factor(middle, pos) - 1.0;
+                (1.0 - f * f).sqrt()
+            }
+            // Sphere decreasing
+            4 => {
+                let f = linear_factor(middle, pos);
+                1.0 - (1.0 - f * f).sqrt()
+            }
+            // Step
+            5 => if pos >= middle { 1.0 } else { 0.0 },
+            _ => linear_factor(middle, pos),
+        };
+
+        let (l, r) = (self.left_color, self.right_color);
+        let rgb = match self.coloring {
+            1 | 2 => {
+                let lh = rgb_to_hsv([l[0], l[1], l[2]]);
+                let rh = rgb_to_hsv([r[0], r[1], r[2]]);
+                // 1 turns counter-clockwise, 2 clockwise around the hue circle
+                let mut delta = rh[0] - lh[0];
+                if self.coloring == 1 && delta < 0.0 {
+                    delta += 1.0;
+                } else if self.coloring == 2 && delta > 0.0 {
+                    delta -= 1.0;
+                }
+                hsv_to_rgb([
+                    (lh[0] + delta * factor).rem_euclid(1.0),
+                    lh[1] + (rh[1] - lh[1]) * factor,
+                    lh[2] + (rh[2] - lh[2]) * factor,
+                ])
+            }
+            _ => [
+                l[0] + (r[0] - l[0]) * factor,
+                l[1] + (r[1] - l[1]) * factor,
+                l[2] + (r[2] - l[2]) * factor,
+            ],
+        };
+
+        [rgb[0], rgb[1], rgb[2], 1.0]
+    }
+}
+
+fn linear_factor(middle: f32, pos: f32) -> f32 {
+    if pos <= middle {
+        if middle < f32::EPSILON { 0.0 } else { 0.5 * pos / middle }
+    } else if middle > 1.0 - f32::EPSILON {
+        1.0
+    } else {
+        0.5 + 0.5 * (pos - middle) / (1.0 - middle)
+    }
+}
+
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta <= f32::EPSILON {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / delta + 2.0) / 6.0
+    } else {
+        ((r - g) / delta + 4.0) / 6.0
+    };
+    let s = if max <= f32::EPSILON { 0.0 } else { delta / max };
+
+    [h, s, max]
+}
+
+fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let h = h * 6.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = v - c;
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [r + m, g + m, b + m]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 4], expected: [f32; 3]) {
+        for i in 0..3 {
+            assert!((actual[i] - expected[i]).abs() < 1e-4, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn segment(middle: f32, blend: u32) -> GradientSegment {
+        GradientSegment {
+            left: 0.0,
+            middle,
+            right: 1.0,
+            left_color: [0.0, 0.0, 0.0, 1.0],
+            right_color: [1.0, 1.0, 1.0, 1.0],
+            blend,
+            coloring: 0,
+        }
+    }
+
+    fn parse_line(error: PaletteError) -> usize {
+        match error {
+            PaletteError::Parse { line, .. } => line,
+            e => panic!("expected a parse error, got {}", e),
+        }
+    }
+
+    #[test]
+    fn map_lists_one_color_per_line() {
+        let colors = parse_map("0 0 0\n\n255 128 0  orange\n").unwrap();
+        assert_eq!(colors.len(), 2);
+        assert_eq!(colors[0], [0.0, 0.0, 0.0, 1.0]);
+        assert_close(colors[1], [1.0, 128.0 / 255.0, 0.0]);
+
+        assert_eq!(parse_line(parse_map("0 0 0\n256 0 0\n").unwrap_err()), 2);
+        assert_eq!(parse_line(parse_map("0 0\n").unwrap_err()), 1);
+    }
+
+    #[test]
+    fn gpl_skips_the_header_fields_and_comments() {
+        let text = "GIMP Palette\nName: Test\nColumns: 4\n#\n# A comment\n255   0   0\tRed\n  0   0 255\tBlue\n";
+        let colors = parse_gpl(text).unwrap();
+        assert_eq!(colors, vec![[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]]);
+
+        assert_eq!(parse_line(parse_gpl("Name: Test\n255 0 0\n").unwrap_err()), 1);
+        assert_eq!(parse_line(parse_gpl("GIMP Palette\n255 0 red\n").unwrap_err()), 2);
+    }
+
+    #[test]
+    fn ggr_samples_its_segments() {
+        let text = "GIMP Gradient\nName: Test\n1\n0 0.5 1 0 0 0 1 1 1 1 1 0 0\n";
+        let colors = parse_ggr(text).unwrap();
+        assert_eq!(colors.len(), GRADIENT_SIZE);
+        for (i, color) in colors.iter().enumerate() {
+            let t = (i as f32 + 0.5) / GRADIENT_SIZE as f32;
+            assert_close(*color, [t, t, t]);
+        }
+
+        // Two halves, the name is optional and blend and coloring default to linear RGB
+        let text = "GIMP Gradient\n2\n0 0.25 0.5 1 0 0 1 1 0 0 1\n0.5 0.75 1 0 0 1 1 0 0 1 1\n";
+        let colors = parse_ggr(text).unwrap();
+        assert_close(colors[0], [1.0, 0.0, 0.0]);
+        assert_close(colors[GRADIENT_SIZE - 1], [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn ggr_errors_point_at_the_line() {
+        assert_eq!(parse_line(parse_ggr("GIMP Palette\n1\n").unwrap_err()), 1);
+        assert_eq!(parse_line(parse_ggr("GIMP Gradient\nName: Test\n").unwrap_err()), 2);
+        assert_eq!(parse_line(parse_ggr("GIMP Gradient\nName: Test\nmany\n").unwrap_err()), 3);
+
+        let error = parse_ggr("GIMP Gradient\n2\n0 0.5 1 0 0 0 1 1 1 1 1\n").unwrap_err();
+        assert_eq!(error.to_string(), "invalid palette on line 2: expected 2 segments, found 1");
+        assert_eq!(parse_line(parse_ggr("GIMP Gradient\n1\n0 0.5 1 0 0 0 1\n").unwrap_err()), 3);
+        assert_eq!(parse_line(parse_ggr("GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 x 1\n").unwrap_err()), 3);
+    }
+
+    #[test]
+    fn blend_functions_at_the_middle() {
+        let factor = |middle, blend| segment(middle, blend).sample(middle)[0];
+        assert!((factor(0.5, 0) - 0.5).abs() < 1e-6);
+        assert!((factor(0.25, 0) - 0.5).abs() < 1e-6);
+        assert!((factor(0.5, 1) - 0.5).abs() < 1e-6);
+        assert!((factor(0.25, 1) - 0.5).abs() < 1e-6);
+        assert!((factor(0.5, 2) - 0.5).abs() < 1e-6);
+        assert!((factor(0.5, 3) - 0.75f32.sqrt()).abs() < 1e-6);
+        assert!((factor(0.5, 4) - (1.0 - 0.75f32.sqrt())).abs() < 1e-6);
+        assert_eq!(factor(0.5, 5), 1.0);
+        assert_eq!(segment(0.5, 5).sample(0.49)[0], 0.0);
+
+        // Every blend runs from the left to the right color
+        for blend in 0..=5 {
+            assert!(segment(0.3, blend).sample(0.0)[0] < 1e-3, "blend {}", blend);
+            assert!(segment(0.3, blend).sample(1.0)[0] > 1.0 - 1e-3, "blend {}", blend);
+        }
+    }
+
+    #[test]
+    fn hsv_coloring_turns_around_the_hue_circle() {
+        let red_to_blue = |coloring| GradientSegment {
+            left_color: [1.0, 0.0, 0.0, 1.0],
+            right_color: [0.0, 0.0, 1.0, 1.0],
+            coloring,
+            ..segment(0.5, 0)
+        };
+        // Counter-clockwise passes green, clockwise magenta
+        assert_close(red_to_blue(1).sample(0.5), [0.0, 1.0, 0.0]);
+        assert_close(red_to_blue(2).sample(0.5), [1.0, 0.0, 1.0]);
+        assert_close(red_to_blue(0).sample(0.5), [0.5, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn hsv_round_trips() {
+        assert_eq!(rgb_to_hsv([1.0, 0.0, 0.0]), [0.0, 1.0, 1.0]);
+        assert_eq!(rgb_to_hsv([0.5, 0.5, 0.5]), [0.0, 0.0, 0.5]);
+        for rgb in [[0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.2, 0.4, 0.6], [0.9, 0.1, 0.5], [1.0, 1.0, 0.0], [0.0, 0.0, 0.0]] {
+            let [r, g, b] = hsv_to_rgb(rgb_to_hsv(rgb));
+            assert_close([r, g, b, 1.0], rgb);
+        }
+    }
+
+    #[test]
+    fn load_rejects_unknown_names_and_missing_files() {
+        assert_eq!(Palette::load("fire").unwrap().name, "fire");
+        assert!(matches!(Palette::load("fire.txt"), Err(PaletteError::UnknownFormat(_))));
+        let error = Palette::load("missing/palette.map").unwrap_err();
+        assert!(matches!(error, PaletteError::Io(ref path, _) if path == Path::new("missing/palette.map")));
+        assert!(Error::from(error).to_string().starts_with("failed to load the palette: cannot read missing/palette.map"));
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+use crate::error::Error;
+use crate::fractal::FractalParams;
+use crate::path_trace::PathSettings;
+use crate::sdf::SdfScene;
+use crate::view::View;
+
+/// What is rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// The escape-time fractal of `view` and `fractal`
+    #[default]
+    Fractal,
+    /// The signed distance scene `sdf` seen through `camera`
+    Sdf,
+    /// The same scene path traced with the materials of its objects and the `path` settings
+    Path,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mode, String> {
+        match s {
+            "fractal" => Ok(Mode::Fractal),
+            "sdf" => Ok(Mode::Sdf),
+            "path" => Ok(Mode::Path),
+            _ => Err(format!("unknown mode {}, expected fractal, sdf or path", s)),
+        }
+    }
+}
+
+/// Everything needed to reproduce a render, saved to and loaded from TOML files. Fields missing
+/// from a file keep their defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Params {
+    pub mode: Mode,
+    /// Built-in palette name or palette file path, see `Palette::load`
+    pub palette: String,
+    /// Size of renders, and of the window at startup
+    pub resolution: [u32; 2],
+    pub view: View,
+    pub fractal: FractalParams,
+    pub camera: Camera,
+    pub sdf: SdfScene,
+    pub path: PathSettings,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            mode: Mode::Fractal,
+            palette: "classic".to_owned(),
+            resolution: [1280, 720],
+            view: View::default(),
+            fractal: FractalParams::default(),
+            camera: Camera::default(),
+            sdf: SdfScene::default(),
+            path: PathSettings::default(),
+        }
+    }
+}
+
+/// Keyword of the PNG text chunk holding the parameters as TOML
+const PNG_KEYWORD: &str = "sel:params";
+
+impl Params {
+    /// Reads a TOML file, or the parameters embedded in a PNG saved with `save_image`.
+    pub fn load(path: &Path) -> Result<Params, Error> {
+        let text = if is_png(path) {
+            read_png_text(path)?
+        } else {
+            fs::read_to_string(path)?
+        };
+        let mut params: Params = toml::from_str(&text)?;
+        params.validate()?;
+
+        // The center is as precise as the file has digits, match it to the zoom instead
+        let fraction_limbs = View::fraction_limbs(params.view.zoom);
+        params.view.center = params.view.center.map(|c| c.with_fraction_limbs(fraction_limbs));
+        Ok(params)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Checks the values the renderers divide by or take logarithms of, and the scene.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |message: &str| Err(Error::InvalidParams(message.to_owned()));
+        if !(self.view.zoom.is_finite() && self.view.zoom > 0.0) {
+            return invalid("the zoom has to be a finite number above 0");
+        }
+        if !self.view.rotation.is_finite() {
+            return invalid("the rotation has to be a finite number");
+        }
+        if self.resolution.contains(&0) {
+            return invalid("the width and height have to be at least 1");
+        }
+        let escape_radius = self.fractal.escape_radius;
+        if !(escape_radius.is_finite() && escape_radius > 1.0) {
+            return invalid("the escape radius has to be a finite number above 1");
+        }
+        self.sdf.validate()
+    }
+}
+
+/// Saves `image` in the format of the file extension. PNGs carry `params` in a text chunk,
+/// so `Params::load` can restore them from the image.
+pub fn save_image(path: &Path, image: &RgbaImage, params: &Params) -> Result<(), Error> {
+    if !is_png(path) {
+        image.save(path)?;
+        return Ok(());
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png_encoder(file, [image.width(), image.height()], params)?;
+    encoder.set_color(png::ColorType::Rgba);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(image.as_raw())?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// An 8 bit PNG encoder with `params` in a text chunk, the colour type is left to the caller.
+pub fn png_encoder<W: Write>(writer: W, size: [u32; 2], params: &Params) -> Result<png::Encoder<'static, W>, Error> {
+    let mut encoder = png::Encoder::new(writer, size[0], size[1]);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.add_text_chunk("Software".to_owned(), "sel".to_owned())?;
+    encoder.add_itxt_chunk(PNG_KEYWORD.to_owned(), toml::to_string(params)?)?;
+    Ok(encoder)
+}
+
+pub fn is_png(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+/// The parameter text chunk of a PNG, which precedes the image data.
+fn read_png_text(path: &Path) -> Result<String, Error> {
+    let reader = png::Decoder::new(BufReader::new(File::open(path)?)).read_info()?;
+    let chunk = reader
+        .info()
+        .utf8_text
+        .iter()
+        .find(|chunk| chunk.keyword == PNG_KEYWORD)
+        .ok_or_else(|| Error::NoParams(path.to_owned()))?;
+    Ok(chunk.get_text()?)
+}
+
+/// The first of `{stem}-001.{extension}`, `{stem}-002.{extension}`, ... that does not exist yet.
+pub fn next_free_path(stem: &str, extension: &str) -> PathBuf {
+    (1..)
+        .map(|i| PathBuf::from(format!("{}-{:03}.{}", stem, i, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::precision::Fixed;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sel-params-{}-{}", std::process::id(), name))
+    }
+
+    /// Deep enough that the center needs more precision than an `f64` holds.
+    fn deep_params() -> Params {
+        let mut params = Params::default();
+        params.view.zoom = 1e25;
+        let fraction_limbs = View::fraction_limbs(params.view.zoom);
+        params.view.center = ["-0.74364388703715870475219150611", "0.13182590420531197049313205533"]
+            .map(|c| c.parse::<Fixed>().unwrap().with_fraction_limbs(fraction_limbs));
+        params.view.rotation = 0.25;
+        params.fractal.max_iterations = 5000;
+        params.resolution = [320, 200];
+        params
+    }
+
+    fn invalid(params: &Params) -> String {
+        match params.validate() {
+            Err(Error::InvalidParams(message)) => message,
+            result => panic!("expected invalid parameters, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn toml_round_trips() {
+        let params = deep_params();
+        let path = temp_path("round-trip.toml");
+        params.save(&path).unwrap();
+        let loaded = Params::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.view.center, params.view.center);
+        assert_eq!(loaded, params);
+    }
+
+    #[test]
+    fn png_round_trips() {
+        let params = deep_params();
+        let [width, height] = params.resolution;
+        let image = RgbaImage::from_fn(width, height, |x, y| image::Rgba([x as u8, y as u8, 128, 255]));
+        let path = temp_path("round-trip.png");
+        save_image(&path, &image, &params).unwrap();
+        let loaded = Params::load(&path);
+        let pixels = image::open(&path).map(|image| image.to_rgba8());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), params);
+        assert_eq!(pixels.unwrap(), image);
+    }
+
+    #[test]
+    fn png_without_params_is_an_error() {
+        let path = temp_path("plain.png");
+        RgbaImage::new(4, 4).save(&path).unwrap();
+        let result = Params::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(&result, Err(Error::NoParams(p)) if *p == path), "{:?}", result);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(Params::default().validate().is_ok());
+
+        for zoom in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let mut params = Params::default();
+            params.view.zoom = zoom;
+            assert!(invalid(&params).contains("zoom"), "{}", zoom);
+        }
+        for resolution in [[0, 720], [1280, 0]] {
+            let params = Params { resolution, ..Params::default() };
+            assert!(invalid(&params).contains("width"), "{:?}", resolution);
+        }
+        for escape_radius in [1.0, 0.5, -4.0, f32::NAN, f32::INFINITY] {
+            let mut params = Params::default();
+            params.fractal.escape_radius = escape_radius;
+            assert!(invalid(&params).contains("escape radius"), "{}", escape_radius);
+        }
+    }
+
+    #[test]
+    fn load_rejects_invalid_files() {
+        let path = temp_path("invalid.toml");
+        fs::write(&path, "[fractal]\nescape_radius = 0.5\n").unwrap();
+        let result = Params::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::InvalidParams(_))), "{:?}", result);
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::SecondaryAutoCommandBuffer;
+use vulkano::image::StorageImage;
+use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::viewport::Viewport;
+
+use crate::camera::Camera;
+use crate::error::Error;
+use crate::fractal::FractalParams;
+use crate::mesh::MeshBuffers;
+use crate::path_trace::PathSettings;
+use crate::sdf::SdfScene;
+use crate::view::View;
+
+/// Where a pass records its commands within a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PassStage {
+    /// Before the render pass, may write the storage image
+    Compute,
+    /// Inside the subpass of the render pass, reads the storage image
+    Graphics,
+}
+
+/// A fractal rendered into part of the storage image.
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub view: View,
+    pub fractal: FractalParams,
+    /// The reference orbit of `view` uploaded with `ReferenceOrbit::upload`, computed while
+    /// recording if the zoom needs one and it is `None`
+    pub orbit: Option<Subbuffer<[[f32; 4]]>>,
+    pub offset: [u32; 2],
+    pub extent: [u32; 2],
+}
+
+/// A signed distance scene ray marched into the whole storage image.
+#[derive(Clone)]
+pub struct RayMarch {
+    pub camera: Camera,
+    pub scene: SdfScene,
+    /// `scene` uploaded with `SdfScene::upload`
+    pub objects: Subbuffer<[[f32; 4]]>,
+    /// The meshes of `scene` uploaded with `Mesh::upload`
+    pub meshes: MeshBuffers,
+}
+
+/// A signed distance scene path traced into the accumulation image, whose average is resolved
+/// into the whole storage image.
+#[derive(Clone)]
+pub struct PathTrace {
+    pub camera: Camera,
+    pub scene: SdfScene,
+    pub settings: PathSettings,
+    /// `scene` uploaded with `SdfScene::upload`
+    pub objects: Subbuffer<[[f32; 4]]>,
+    /// The meshes of `scene` uploaded with `Mesh::upload`
+    pub meshes: MeshBuffers,
+}
+
+/// What to render, regions are drawn in order so later ones overlap earlier ones.
+#[derive(Clone)]
+pub struct Scene {
+    pub palette: Subbuffer<[[f32; 4]]>,
+    pub regions: Vec<Region>,
+    /// Drawn after the regions
+    pub ray_march: Option<RayMarch>,
+    /// Drawn after the regions and the ray marched scene
+    pub path_trace: Option<PathTrace>,
+}
+
+/// Values that change from frame to frame, written before every submission of a frame so that
+/// its command buffers do not have to be recorded again. Matches the `FrameUniforms` blocks of
+/// the compute shaders.
+#[derive(Clone, Copy, Debug, Default, BufferContents)]
+#[repr(C)]
+pub struct FrameUniforms {
+    /// Samples in the accumulation image of the path tracer before the frame
+    pub frame_index: u32,
+    /// Added to the palette offset of every fractal region, in palette lengths
+    pub palette_shift: f32,
+}
+
+impl FrameUniforms {
+    /// Host writable uniform buffer holding `self`.
+    pub fn upload(self, memory_allocator: &StandardMemoryAllocator) -> Result<Subbuffer<Self>, Error> {
+        Ok(Buffer::from_data(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::UNIFORM_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Upload,
+                ..Default::default()
+            },
+            self,
+        )?)
+    }
+}
+
+/// Everything a pass needs to record its commands for one framebuffer.
+pub struct Frame<'a> {
+    pub image_view: &'a Arc<ImageView<StorageImage>>,
+    /// Running average of the path tracer, the size of the storage image
+    pub accumulation_view: &'a Arc<ImageView<StorageImage>>,
+    /// Written before every submission of the frame
+    pub uniforms: &'a Subbuffer<FrameUniforms>,
+    pub viewport: &'a Viewport,
+    pub scene: &'a Scene,
+}
+
+/// A step of the frame, recorded into secondary command buffers that the `Renderer` executes
+/// in the order the passes were added.
+pub trait Pass {
+    fn stage(&self) -> PassStage;
+
+    fn record(&self, frame: &Frame) -> Result<Vec<SecondaryAutoCommandBuffer>, Error>;
+}
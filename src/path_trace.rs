@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// Settings of the progressive path tracer, which renders the signed distance scene with the
+/// materials of its objects, lit by the sky and emissive surfaces.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathSettings {
+    /// Paths averaged per pixel, the window stops refining the image after this many frames
+    pub samples: u32,
+    /// Surfaces a path scatters off before it is given up as dark
+    pub max_bounces: u32,
+    /// Seed of the random numbers, renders with the same seed are identical
+    pub seed: u32,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings {
+            samples: 256,
+            max_bounces: 8,
+            seed: 0,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
+
+use crate::error::Error;
+use crate::fractal::{FractalKind, FractalParams};
+use crate::precision::{self, Fixed};
+use crate::view::View;
+
+/// Exponent of zero, must match `EXPONENT_ZERO` in the perturbation shader
+const EXPONENT_ZERO: i32 = -1_000_000;
+/// The reference stops at this radius even if the escape radius is larger, pixels rebase when
+/// they run past its end. Keeps the fixed-point integer part from overflowing.
+const MAX_REFERENCE_RADIUS: f64 = 256.0;
+
+/// Orbit of the view center iterated in fixed-point, the reference the perturbation kernel
+/// iterates pixel deltas against.
+pub struct ReferenceOrbit {
+    /// Points as (mantissa.x, mantissa.y, exponent, unused), see `Complex` in the shader
+    pub points: Vec<[f32; 4]>,
+}
+
+impl ReferenceOrbit {
+    /// Iterates until the reference escapes or reaches `max_iterations`.
+    pub fn new(view: &View, fractal: &FractalParams) -> ReferenceOrbit {
+        let fraction_limbs = view.center[0].fraction_limbs();
+        let (mut x, mut y, cx, cy) = match fractal.kind {
+            FractalKind::Mandelbrot => (
+                Fixed::from_f64(0.0, fraction_limbs),
+                Fixed::from_f64(0.0, fraction_limbs),
+                view.center[0].clone(),
+                view.center[1].clone(),
+            ),
+            FractalKind::Julia => (
+                view.center[0].clone(),
+                view.center[1].clone(),
+                Fixed::from_f64(fractal.julia_c[0], fraction_limbs),
+                Fixed::from_f64(fractal.julia_c[1], fraction_limbs),
+            ),
+        };
+
+        let escape_radius = (fractal.escape_radius as f64).min(MAX_REFERENCE_RADIUS);
+        let escape_radius2 = escape_radius * escape_radius;
+
+        let mut points = vec![point(&x, &y)];
+        // The kernel reads one point ahead of the reference index
+        while points.len() < 2 || points.len() <= fractal.max_iterations as usize {
+            let x2 = &x * &x;
+            let y2 = &y * &y;
+            let xy = &x * &y;
+            x = &(&x2 - &y2) + &cx;
+            y = &(&xy + &xy) + &cy;
+            points.push(point(&x, &y));
+
+            let (x, y) = (x.to_f64(), y.to_f64());
+            if x * x + y * y > escape_radius2 {
+                break;
+            }
+        }
+
+        ReferenceOrbit { points }
+    }
+
+    pub fn upload(&self, memory_allocator: &StandardMemoryAllocator) -> Result<Subbuffer<[[f32; 4]]>, Error> {
+        Ok(Buffer::from_iter(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Upload,
+                ..Default::default()
+            },
+            self.points.iter().copied(),
+        )?)
+    }
+}
+
+/// Splits `value` into a float mantissa and exponent as the perturbation kernel expects.
+pub fn split_exponent(value: f64) -> (f32, i32) {
+    let (mantissa, exponent) = precision::frexp(value);
+    (mantissa as f32, exponent)
+}
+
+/// Both components share the exponent of the larger one, like `Complex` in the shader.
+fn point(x: &Fixed, y: &Fixed) -> [f32; 4] {
+    let (x, y) = (x.to_f64(), y.to_f64());
+    let (_, exponent) = precision::frexp(x.abs().max(y.abs()));
+    if x == 0.0 && y == 0.0 {
+        return [0.0, 0.0, EXPONENT_ZERO as f32, 0.0];
+    }
+
+    [
+        precision::ldexp(x, -exponent) as f32,
+        precision::ldexp(y, -exponent) as f32,
+        exponent as f32,
+        0.0,
+    ]
+}
@@ -0,0 +1,410 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use image::RgbaImage;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::headless::HeadlessRenderer;
+use crate::palette::Palette;
+use crate::params::{self, Params};
+
+/// Settings of the render, kept with the finished bands to recognize them when resuming
+const MANIFEST: &str = "poster.toml";
+
+/// A render larger than a single image, rendered in tiles and stitched into a PNG or TIFF.
+#[derive(Clone, Debug, Serialize)]
+pub struct Poster {
+    /// The resolution is the size of the output
+    pub params: Params,
+    /// Each output pixel averages `supersampling²` rendered pixels
+    pub supersampling: u32,
+    /// Output pixels per tile side, reduced if the device cannot create images that large
+    pub tile_size: u32,
+}
+
+impl Poster {
+    /// Renders to `output`, a `.png`, `.tif` or `.tiff` file. Tiles are rendered in bands of one
+    /// tile height, each finished band is kept in a `.parts` directory next to the output until
+    /// the image is written. Running an interrupted export again with the same settings resumes
+    /// after the last finished band.
+    pub fn render(&self, renderer: &HeadlessRenderer, palette: &Palette, output: &Path) -> Result<(), Error> {
+        let format = OutputFormat::from_path(output)?;
+        let supersampling = self.supersampling.max(1);
+        let max_dimension = renderer.context().physical_device.properties().max_image_dimension2_d;
+        let poster = Poster {
+            params: self.params.clone(),
+            supersampling,
+            tile_size: self.tile_size.min(max_dimension / supersampling).max(1),
+        };
+
+        let [width, height] = poster.params.resolution;
+        let bands = height.div_ceil(poster.tile_size);
+        let parts = output.with_extension("parts");
+        poster.prepare_parts(&parts)?;
+
+        let start = Instant::now();
+        let mut rendered = 0;
+        for band in 0..bands {
+            let path = band_path(&parts, band);
+            if path.exists() {
+                continue;
+            }
+
+            let pixels = poster.render_band(renderer, palette, band)?;
+            // A band file that exists is complete, so write it under another name first
+            let temporary = path.with_extension("tmp");
+            fs::write(&temporary, pixels)?;
+            fs::rename(&temporary, &path)?;
+
+            rendered += 1;
+            let remaining = bands - band - 1;
+            let eta = start.elapsed() / rendered * remaining;
+            tracing::info!(
+                "Band {} of {} done, {:.1}%, about {}s left",
+                band + 1,
+                bands,
+                100.0 * (band + 1) as f64 / bands as f64,
+                eta.as_secs(),
+            );
+        }
+
+        let strips: Vec<_> = (0..bands).map(|band| band_path(&parts, band)).collect();
+        match format {
+            OutputFormat::Png => poster.write_png(output, &strips)?,
+            OutputFormat::Tiff => poster.write_tiff(output, &strips)?,
+        }
+        fs::remove_dir_all(&parts)?;
+
+        tracing::info!("Saved {}x{} poster to {}", width, height, output.display());
+        Ok(())
+    }
+
+    /// Creates the parts directory, or keeps its bands if they were rendered with the same settings.
+    fn prepare_parts(&self, parts: &Path) -> Result<(), Error> {
+        let manifest = toml::to_string(self)?;
+        let manifest_path = parts.join(MANIFEST);
+        if parts.exists() {
+            if fs::read_to_string(&manifest_path).is_ok_and(|existing| existing == manifest) {
+                let done = fs::read_dir(parts)?
+                    .filter(|entry| entry.as_ref().is_ok_and(|e| e.path().extension().is_some_and(|e| e == "rgb")))
+                    .count();
+                tracing::info!("Resuming from {}, {} bands done", parts.display(), done);
+                return Ok(());
+            }
+            tracing::warn!("{} holds a render with other settings, starting over", parts.display());
+            fs::remove_dir_all(parts)?;
+        }
+
+        fs::create_dir_all(parts)?;
+        fs::write(manifest_path, manifest)?;
+        Ok(())
+    }
+
+    /// RGB rows of the band, with every tile rendered at `supersampling` times the resolution
+    /// and averaged down.
+    fn render_band(&self, renderer: &HeadlessRenderer, palette: &Palette, band: u32) -> Result<Vec<u8>, Error> {
+        let [width, height] = self.params.resolution;
+        let top = band * self.tile_size;
+        let band_height = self.tile_size.min(height - top);
+        let mut pixels = vec![0; width as usize * band_height as usize * 3];
+
+        for left in (0..width).step_by(self.tile_size as usize) {
+            let tile_width = self.tile_size.min(width - left);
+            let view = self.params.view.tile(
+                [width as f64, height as f64],
+                [left as f64, top as f64],
+                [tile_width as f64, band_height as f64],
+            );
+            let tile = renderer.render(
+                [tile_width * self.supersampling, band_height * self.supersampling],
+                &view,
+                &self.params.fractal,
+                palette,
+            )?;
+            downsample(&tile, self.supersampling, &mut pixels, width as usize, left as usize);
+        }
+
+        Ok(pixels)
+    }
+
+    fn write_png(&self, output: &Path, strips: &[PathBuf]) -> Result<(), Error> {
+        let file = BufWriter::new(File::create(output)?);
+        let mut encoder = params::png_encoder(file, self.params.resolution, &self.params)?;
+        encoder.set_color(png::ColorType::Rgb);
+
+        // The stream writer splits the bands into rows, only one band is in memory at a time
+        let mut stream = encoder.write_header()?.into_stream_writer()?;
+        for strip in strips {
+            io::copy(&mut File::open(strip)?, &mut stream)?;
+        }
+        stream.finish()?;
+        Ok(())
+    }
+
+    /// Baseline uncompressed RGB TIFF with one strip per band and the parameters as the image
+    /// description. Offsets are 32 bit, so the image data has to stay below 4 GiB.
+    fn write_tiff(&self, output: &Path, strips: &[PathBuf]) -> Result<(), Error> {
+        const ASCII: u16 = 2;
+        const SHORT: u16 = 3;
+        const LONG: u16 = 4;
+
+        let [width, height] = self.params.resolution;
+        let strip_sizes: Vec<u64> = (0..strips.len() as u32)
+            .map(|band| width as u64 * self.tile_size.min(height - band * self.tile_size) as u64 * 3)
+            .collect();
+        let mut description = toml::to_string(&self.params)?.into_bytes();
+        description.push(0);
+
+        let longs = |values: &[u32]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+        let shorts = |values: &[u16]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+        let strip_byte_counts: Vec<u32> = strip_sizes.iter().map(|size| *size as u32).collect();
+        // Tag, type, count and data, sorted by tag. The strip offsets are filled in below.
+        let mut entries = vec![
+            (256, LONG, 1, longs(&[width])),
+            (257, LONG, 1, longs(&[height])),
+            (258, SHORT, 3, shorts(&[8, 8, 8])),
+            (259, SHORT, 1, shorts(&[1])),
+            (262, SHORT, 1, shorts(&[2])),
+            (270, ASCII, description.len() as u32, description),
+            (273, LONG, strips.len() as u32, longs(&vec![0; strips.len()])),
+            (277, SHORT, 1, shorts(&[3])),
+            (278, LONG, 1, longs(&[self.tile_size])),
+            (279, LONG, strips.len() as u32, longs(&strip_byte_counts)),
+            (284, SHORT, 1, shorts(&[1])),
+        ];
+
+        // Header, directory, the data of entries that do not fit into 4 bytes, then the strips
+        let directory_size = 2 + 12 * entries.len() as u64 + 4;
+        let extra_size: u64 = entries
+            .iter()
+            .filter(|(_, _, _, data)| data.len() > 4)
+            .map(|(_, _, _, data)| (data.len() as u64).next_multiple_of(2))
+            .sum();
+        let data_start = 8 + directory_size + extra_size;
+        let end = data_start + strip_sizes.iter().sum::<u64>();
+        if end > u32::MAX as u64 {
+            return Err(Error::Poster("TIFF files are limited to 4 GiB, export a PNG instead".to_owned()));
+        }
+
+        let strip_offsets: Vec<u32> = strip_sizes
+            .iter()
+            .scan(data_start, |offset, size| {
+                let strip_offset = *offset;
+                *offset += size;
+                Some(strip_offset as u32)
+            })
+            .collect();
+        entries[6].3 = longs(&strip_offsets);
+
+        let mut file = BufWriter::new(File::create(output)?);
+        file.write_all(b"II")?;
+        file.write_all(&42u16.to_le_bytes())?;
+        file.write_all(&8u32.to_le_bytes())?;
+
+        file.write_all(&(entries.len() as u16).to_le_bytes())?;
+        let mut extra_offset = 8 + directory_size;
+        for (tag, kind, count, data) in &entries {
+            file.write_all(&(*tag as u16).to_le_bytes())?;
+            file.write_all(&kind.to_le_bytes())?;
+            file.write_all(&count.to_le_bytes())?;
+            if data.len() <= 4 {
+                let mut value = [0; 4];
+                value[..data.len()].copy_from_slice(data);
+                file.write_all(&value)?;
+            } else {
+                file.write_all(&(extra_offset as u32).to_le_bytes())?;
+                extra_offset += (data.len() as u64).next_multiple_of(2);
+            }
+        }
+        file.write_all(&0u32.to_le_bytes())?;
+
+        for (_, _, _, data) in entries.iter().filter(|(_, _, _, data)| data.len() > 4) {
+            file.write_all(data)?;
+            if data.len() % 2 == 1 {
+                file.write_all(&[0])?;
+            }
+        }
+        for strip in strips {
+            io::copy(&mut File::open(strip)?, &mut file)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+enum OutputFormat {
+    Png,
+    Tiff,
+}
+
+impl OutputFormat {
+    fn from_path(path: &Path) -> Result<OutputFormat, Error> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => Ok(OutputFormat::Png),
+            Some("tif" | "tiff") => Ok(OutputFormat::Tiff),
+            _ => Err(Error::Poster(format!("{} is not a .png, .tif or .tiff file", path.display()))),
+        }
+    }
+}
+
+fn band_path(parts: &Path, band: u32) -> PathBuf {
+    parts.join(format!("band-{:05}.rgb", band))
+}
+
+/// Averages blocks of `factor`² pixels of `tile` into the RGB rows of `band`, starting at column `left`.
+fn downsample(tile: &RgbaImage, factor: u32, band: &mut [u8], band_width: usize, left: usize) {
+    let width = tile.width() / factor;
+    let height = tile.height() / factor;
+    let samples = factor * factor;
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0u32; 3];
+            for sy in 0..factor {
+                for sx in 0..factor {
+                    let pixel = tile.get_pixel(x * factor + sx, y * factor + sy);
+                    for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                        *sum += channel as u32;
+                    }
+                }
+            }
+
+            let i = (y as usize * band_width + left + x as usize) * 3;
+            for (out, sum) in band[i..i + 3].iter_mut().zip(sum) {
+                *out = ((sum + samples / 2) / samples) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::view::View;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("sel-poster-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn downsample_averages_blocks_into_the_band() {
+        // Two output pixels from a 4x2 tile, rounded to the nearest value
+        let tile = RgbaImage::from_raw(4, 2, [
+            [0, 10, 255, 255], [0, 20, 255, 255], [100, 0, 1, 255], [101, 0, 1, 255],
+            [0, 30, 255, 255], [1, 40, 255, 255], [100, 0, 2, 255], [101, 0, 2, 255],
+        ].concat()).unwrap();
+        let mut band = vec![7; 3 * 3];
+        downsample(&tile, 2, &mut band, 3, 1);
+        assert_eq!(band, [7, 7, 7, 0, 25, 255, 101, 0, 2]);
+
+        // A factor of 1 copies the colours and drops the alpha
+        let mut band = vec![0; 4 * 2 * 3];
+        downsample(&tile, 1, &mut band, 4, 0);
+        assert_eq!(&band[..6], [0, 10, 255, 0, 20, 255]);
+        assert_eq!(&band[21..], [101, 0, 2]);
+    }
+
+    #[test]
+    fn tiles_cover_the_view_once() {
+        let mut view = View::new([-0.75, 0.1], 3.0);
+        view.rotation = 0.4;
+        let [width, height] = [10u32, 7];
+        let size = [width as f64, height as f64];
+        let tile_size = 4;
+
+        // Laid out like `render_band`, with smaller tiles along the right and bottom edges
+        let mut covered = vec![0; (width * height) as usize];
+        for top in (0..height).step_by(tile_size as usize) {
+            let tile_height = tile_size.min(height - top);
+            for left in (0..width).step_by(tile_size as usize) {
+                let tile_width = tile_size.min(width - left);
+                let extent = [tile_width as f64, tile_height as f64];
+                let tile = view.tile(size, [left as f64, top as f64], extent);
+
+                for y in 0..tile_height {
+                    for x in 0..tile_width {
+                        let (global_x, global_y) = (left + x, top + y);
+                        covered[(global_y * width + global_x) as usize] += 1;
+
+                        let local = tile.pixel_to_plane([x as f64 + 0.5, y as f64 + 0.5], extent);
+                        let global = view.pixel_to_plane([global_x as f64 + 0.5, global_y as f64 + 0.5], size);
+                        for i in 0..2 {
+                            assert!((local[i] - global[i]).abs() < 1e-12, "{:?} != {:?}", local, global);
+                        }
+                    }
+                }
+            }
+        }
+        assert!(covered.iter().all(|count| *count == 1), "{:?}", covered);
+    }
+
+    #[test]
+    fn tiff_offsets_point_at_their_data() {
+        let directory = temp_dir("tiff");
+        let params = Params { resolution: [2, 2], ..Params::default() };
+        let poster = Poster { params, supersampling: 1, tile_size: 1 };
+        let strips = [band_path(&directory, 0), band_path(&directory, 1)];
+        fs::write(&strips[0], [1, 2, 3, 4, 5, 6]).unwrap();
+        fs::write(&strips[1], [7, 8, 9, 10, 11, 12]).unwrap();
+        let output = directory.join("poster.tif");
+        poster.write_tiff(&output, &strips).unwrap();
+        let bytes = fs::read(&output).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(&bytes[..4], b"II\x2a\x00");
+        assert_eq!(u32_at(&bytes, 4), 8);
+        let count = u16_at(&bytes, 8) as usize;
+        assert_eq!(count, 11);
+        let directory_end = 8 + 2 + 12 * count;
+        assert_eq!(u32_at(&bytes, directory_end), 0, "there is a single directory");
+
+        // Entries are sorted by tag, values that do not fit follow the directory in order
+        let entry = |tag: u16| {
+            let i = (0..count).find(|i| u16_at(&bytes, 10 + 12 * i) == tag).unwrap();
+            let start = 10 + 12 * i;
+            (u32_at(&bytes, start + 4) as usize, start + 8)
+        };
+        let tags: Vec<_> = (0..count).map(|i| u16_at(&bytes, 10 + 12 * i)).collect();
+        assert!(tags.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", tags);
+        assert_eq!(u32_at(&bytes, entry(256).1), 2);
+        assert_eq!(u32_at(&bytes, entry(257).1), 2);
+        assert_eq!(u32_at(&bytes, entry(278).1), 1);
+
+        let bits_offset = u32_at(&bytes, entry(258).1) as usize;
+        assert_eq!(bits_offset, directory_end + 4);
+        assert_eq!([0, 2, 4].map(|i| u16_at(&bytes, bits_offset + i)), [8, 8, 8]);
+
+        let (description_length, description_value) = entry(270);
+        let description_offset = u32_at(&bytes, description_value) as usize;
+        assert_eq!(description_offset, bits_offset + 6);
+        let description = &bytes[description_offset..description_offset + description_length];
+        assert_eq!(description, format!("{}\0", toml::to_string(&poster.params).unwrap()).as_bytes());
+
+        let (strip_count, offsets_value) = entry(273);
+        let (_, counts_value) = entry(279);
+        assert_eq!(strip_count, 2);
+        let offsets_offset = u32_at(&bytes, offsets_value) as usize;
+        assert_eq!(offsets_offset, (description_offset + description_length).next_multiple_of(2));
+        let counts_offset = u32_at(&bytes, counts_value) as usize;
+        assert_eq!(counts_offset, offsets_offset + 8);
+        let strip_offsets = [0, 4].map(|i| u32_at(&bytes, offsets_offset + i) as usize);
+        assert_eq!([0, 4].map(|i| u32_at(&bytes, counts_offset + i)), [6, 6]);
+        assert_eq!(strip_offsets[0], counts_offset + 8);
+        assert_eq!(&bytes[strip_offsets[0]..strip_offsets[0] + 6], [1, 2, 3, 4, 5, 6]);
+        assert_eq!(&bytes[strip_offsets[1]..], [7, 8, 9, 10, 11, 12]);
+    }
+}
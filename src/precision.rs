@@ -0,0 +1,529 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Largest integer part of a `Fixed`
+const MAX_INTEGER: f64 = u32::MAX as f64;
+
+/// Signed fixed-point number with a 32 bit integer part and a variable number of 32 bit
+/// fraction limbs. Used for coordinates that need more precision than an `f64` holds.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Fixed {
+    negative: bool,
+    /// Magnitude, least significant limb first. The last limb is the integer part.
+    limbs: Vec<u32>,
+}
+
+impl Default for Fixed {
+    /// Zero with an integer limb and no fraction limbs.
+    fn default() -> Self {
+        Fixed::from_f64(0.0, 0)
+    }
+}
+
+impl Fixed {
+    /// Converts `value` exactly, as far as `fraction_limbs` allow. Magnitudes whose integer part
+    /// does not fit in 32 bits saturate to the largest integer part, NaN converts to zero.
+    pub fn from_f64(value: f64, fraction_limbs: usize) -> Fixed {
+        if value.is_nan() {
+            return Fixed::from_f64(0.0, fraction_limbs);
+        }
+        let magnitude = value.abs();
+        if magnitude > MAX_INTEGER {
+            tracing::warn!("{} does not fit the integer part of a fixed-point number, saturating", value);
+        }
+        let magnitude = magnitude.min(MAX_INTEGER);
+
+        let mut limbs = vec![0; fraction_limbs + 1];
+        limbs[fraction_limbs] = magnitude as u32;
+
+        // Scaling by 2^32 and taking the integer part is exact in floating point
+        let mut fraction = magnitude.fract();
+        for limb in limbs[..fraction_limbs].iter_mut().rev() {
+            if fraction == 0.0 {
+                break;
+            }
+            fraction *= 4294967296.0;
+            *limb = fraction as u32;
+            fraction = fraction.fract();
+        }
+
+        Fixed { negative: value < 0.0, limbs }.normalized()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Only the leading limbs contribute to the 53 bits of an f64, tiny values still keep their exponent
+        let fraction_limbs = self.fraction_limbs();
+        let value = self.limbs
+            .iter()
+            .enumerate()
+            .rev()
+            .skip_while(|(_, limb)| **limb == 0)
+            .take(3)
+            .map(|(i, limb)| ldexp(*limb as f64, 32 * (i as i32 - fraction_limbs as i32)))
+            .sum::<f64>();
+
+        if self.negative { -value } else { value }
+    }
+
+    pub fn fraction_limbs(&self) -> usize {
+        self.limbs.len().saturating_sub(1)
+    }
+
+    /// Changes the precision to `fraction_limbs`, truncating towards zero if it shrinks.
+    pub fn with_fraction_limbs(&self, fraction_limbs: usize) -> Fixed {
+        let current = self.fraction_limbs();
+        let limbs = if fraction_limbs >= current {
+            let mut limbs = vec![0; fraction_limbs - current];
+            limbs.extend_from_slice(&self.limbs);
+            limbs
+        } else {
+            self.limbs[current - fraction_limbs..].to_vec()
+        };
+
+        Fixed { negative: self.negative, limbs }.normalized()
+    }
+
+    /// Divides the magnitude by a small `divisor` in place, truncating.
+    fn div_small(&mut self, divisor: u32) {
+        let mut remainder = 0u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let dividend = (remainder << 32) | *limb as u64;
+            *limb = (dividend / divisor as u64) as u32;
+            remainder = dividend % divisor as u64;
+        }
+    }
+
+    /// Adds `value`, keeping the current precision.
+    pub fn add_f64(&self, value: f64) -> Fixed {
+        self + &Fixed::from_f64(value, self.fraction_limbs())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.limbs.iter().all(|limb| *limb == 0)
+    }
+
+    /// Zero is never negative, so that equal values compare equal.
+    fn normalized(mut self) -> Fixed {
+        if self.limbs.is_empty() {
+            self.limbs.push(0);
+        }
+        if self.is_zero() {
+            self.negative = false;
+        }
+        self
+    }
+
+    /// Both operands resized to the larger precision.
+    fn aligned(&self, other: &Fixed) -> (Fixed, Fixed) {
+        let fraction_limbs = self.fraction_limbs().max(other.fraction_limbs());
+        (self.with_fraction_limbs(fraction_limbs), other.with_fraction_limbs(fraction_limbs))
+    }
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    a.iter().rev().cmp(b.iter().rev())
+}
+
+/// `a + b` for magnitudes of equal length, overflow of the integer part is dropped.
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut carry = 0u64;
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| {
+            let sum = *a as u64 + *b as u64 + carry;
+            carry = sum >> 32;
+            sum as u32
+        })
+        .collect()
+}
+
+/// `a - b` for magnitudes of equal length with `a >= b`.
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut borrow = 0i64;
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| {
+            let difference = *a as i64 - *b as i64 - borrow;
+            borrow = (difference < 0) as i64;
+            difference.rem_euclid(1 << 32) as u32
+        })
+        .collect()
+}
+
+impl Add for &Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: &Fixed) -> Fixed {
+        let (a, b) = self.aligned(other);
+
+        let result = if a.negative == b.negative {
+            Fixed { negative: a.negative, limbs: add_magnitudes(&a.limbs, &b.limbs) }
+        } else if compare_magnitudes(&a.limbs, &b.limbs) == Ordering::Less {
+            Fixed { negative: b.negative, limbs: sub_magnitudes(&b.limbs, &a.limbs) }
+        } else {
+            Fixed { negative: a.negative, limbs: sub_magnitudes(&a.limbs, &b.limbs) }
+        };
+
+        result.normalized()
+    }
+}
+
+impl Neg for &Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed { negative: !self.negative, limbs: self.limbs.clone() }.normalized()
+    }
+}
+
+impl Sub for &Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: &Fixed) -> Fixed {
+        self + &-other
+    }
+}
+
+impl Mul for &Fixed {
+    type Output = Fixed;
+
+    /// Truncates to the larger precision of both operands.
+    fn mul(self, other: &Fixed) -> Fixed {
+        let (a, b) = self.aligned(other);
+        let fraction_limbs = a.fraction_limbs();
+
+        // Schoolbook multiplication, the product has twice the fraction limbs
+        let mut product = vec![0u32; 2 * a.limbs.len()];
+        for (i, a_limb) in a.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, b_limb) in b.limbs.iter().enumerate() {
+                let sum = *a_limb as u64 * *b_limb as u64 + product[i + j] as u64 + carry;
+                product[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            product[i + b.limbs.len()] = carry as u32;
+        }
+
+        Fixed {
+            negative: a.negative != b.negative,
+            limbs: product[fraction_limbs..fraction_limbs + a.limbs.len()].to_vec(),
+        }.normalized()
+    }
+}
+
+impl fmt::Display for Fixed {
+    /// Decimal with as many digits as the precision resolves, trailing zeros removed. The last
+    /// digit rounds the magnitude up, so parsing it and truncating to the same fraction limbs
+    /// gives the value back.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fraction_limbs = self.fraction_limbs();
+        let integer = self.limbs[fraction_limbs];
+
+        // Each multiplication by 10 shifts the next digit into the integer part
+        let digits = (fraction_limbs as f64 * 32.0 * std::f64::consts::LOG10_2).ceil() as usize;
+        let mut fraction = self.limbs[..fraction_limbs].to_vec();
+        let mut decimals = Vec::with_capacity(digits);
+        for _ in 0..digits {
+            let mut carry = 0u64;
+            for limb in fraction.iter_mut() {
+                let product = *limb as u64 * 10 + carry;
+                *limb = product as u32;
+                carry = product >> 32;
+            }
+            decimals.push(b'0' + carry as u8);
+        }
+
+        // The last digit is worth less than a fraction ulp, so rounding up stays below the next
+        // value and never carries into the integer part
+        if fraction.iter().any(|limb| *limb != 0) {
+            if let Some(i) = decimals.iter().rposition(|digit| *digit != b'9') {
+                decimals[i] += 1;
+                decimals[i + 1..].fill(b'0');
+            }
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", integer)?;
+        let decimals = std::str::from_utf8(&decimals).unwrap_or_default().trim_end_matches('0');
+        if !decimals.is_empty() {
+            write!(f, ".{}", decimals)?;
+        }
+        Ok(())
+    }
+}
+
+/// A string that is not a decimal number with an integer part that fits in 32 bits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseFixedError;
+
+impl fmt::Display for ParseFixedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid fixed-point number")
+    }
+}
+
+impl std::error::Error for ParseFixedError {}
+
+impl FromStr for Fixed {
+    type Err = ParseFixedError;
+
+    /// Parses a decimal like `-0.743643887037158704752191506114774`, with enough fraction limbs
+    /// for all of its digits.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (integer, decimals) = digits.split_once('.').unwrap_or((digits, ""));
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if integer.is_empty() && decimals.is_empty() || !is_digits(integer) || !is_digits(decimals) {
+            return Err(ParseFixedError);
+        }
+
+        let fraction_limbs = (decimals.len() as f64 * std::f64::consts::LOG2_10 / 32.0).ceil() as usize;
+        let mut value = Fixed { negative, limbs: vec![0; fraction_limbs + 1] };
+
+        // Horner's scheme from the last decimal: each digit enters the integer part and the
+        // division by 10 shifts it into the fraction
+        for digit in decimals.bytes().rev() {
+            value.limbs[fraction_limbs] = (digit - b'0') as u32;
+            value.div_small(10);
+        }
+        value.limbs[fraction_limbs] = if integer.is_empty() {
+            0
+        } else {
+            integer.parse().map_err(|_| ParseFixedError)?
+        };
+
+        Ok(value.normalized())
+    }
+}
+
+impl Serialize for Fixed {
+    /// As a decimal string, a float would lose the precision.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fixed {
+    /// From a decimal string, or from a number for hand written files.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FixedVisitor;
+
+        impl Visitor<'_> for FixedVisitor {
+            type Value = Fixed;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a decimal number or a string holding one")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Fixed, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Fixed, E> {
+                if value.is_nan() || value.abs() > MAX_INTEGER {
+                    return Err(E::custom(ParseFixedError));
+                }
+                Ok(Fixed::from_f64(value, 2))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Fixed, E> {
+                self.visit_f64(value as f64)
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Fixed, E> {
+                self.visit_f64(value as f64)
+            }
+        }
+
+        deserializer.deserialize_any(FixedVisitor)
+    }
+}
+
+impl fmt::Debug for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// `value * 2^exponent`, for exponents beyond the range of a single power of two.
+pub fn ldexp(value: f64, exponent: i32) -> f64 {
+    let half = exponent / 2;
+    value * 2f64.powi(half) * 2f64.powi(exponent - half)
+}
+
+/// Splits `value` into a mantissa with magnitude in [0.5, 1) and a power of two exponent.
+/// Zero has an exponent of 0.
+pub fn frexp(value: f64) -> (f64, i32) {
+    if value == 0.0 || !value.is_finite() {
+        return (value, 0);
+    }
+
+    let exponent = value.abs().log2().floor() as i32 + 1;
+    let mantissa = ldexp(value, -exponent);
+    // log2 can be off by one close to powers of two
+    if mantissa.abs() >= 1.0 {
+        (mantissa * 0.5, exponent + 1)
+    } else if mantissa.abs() < 0.5 {
+        (mantissa * 2.0, exponent - 1)
+    } else {
+        (mantissa, exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(value: f64) -> Fixed {
+        Fixed::from_f64(value, 2)
+    }
+
+    #[test]
+    fn from_f64_round_trips() {
+        for value in [0.0, 0.5, -1.25, std::f64::consts::PI, -0.7436438870371587, 4294967295.0] {
+            assert_eq!(fixed(value).to_f64(), value);
+        }
+        // Below the first fraction limb
+        let tiny = ldexp(1.0, -80);
+        assert_eq!(Fixed::from_f64(tiny, 3).to_f64(), tiny);
+        assert_eq!(Fixed::from_f64(tiny, 2).to_f64(), 0.0);
+    }
+
+    #[test]
+    fn from_f64_saturates() {
+        assert_eq!(fixed(1e20).to_f64(), MAX_INTEGER);
+        assert_eq!(fixed(-4294967296.0).to_f64(), -MAX_INTEGER);
+        assert_eq!(fixed(f64::INFINITY).to_f64(), MAX_INTEGER);
+        assert_eq!(fixed(f64::NAN), fixed(0.0));
+    }
+
+    #[test]
+    fn arithmetic_matches_f64() {
+        let values = [0.0, 0.25, -0.75, 1.5, -2.125, 3.0517578125e-5, 1000.0625];
+        for a in values {
+            for b in values {
+                assert_eq!((&fixed(a) + &fixed(b)).to_f64(), a + b, "{} + {}", a, b);
+                assert_eq!((&fixed(a) - &fixed(b)).to_f64(), a - b, "{} - {}", a, b);
+                assert_eq!((&fixed(a) * &fixed(b)).to_f64(), a * b, "{} * {}", a, b);
+            }
+        }
+        assert_eq!(fixed(0.5).add_f64(-0.75).to_f64(), -0.25);
+    }
+
+    #[test]
+    fn default_is_a_printable_zero() {
+        let zero = Fixed::default();
+        assert_eq!(zero.fraction_limbs(), 0);
+        assert_eq!(zero.to_f64(), 0.0);
+        assert_eq!(zero.to_string(), "0");
+        assert_eq!((&zero + &fixed(0.5)).to_f64(), 0.5);
+    }
+
+    #[test]
+    fn zero_is_never_negative() {
+        assert_eq!(&fixed(0.5) - &fixed(0.5), fixed(0.0));
+        assert_eq!(&fixed(-0.5) * &fixed(0.0), fixed(0.0));
+        assert_eq!(-&fixed(0.0), fixed(0.0));
+    }
+
+    #[test]
+    fn multiplication_keeps_the_precision() {
+        // (1 + 2^-80)^2 = 1 + 2^-79 + 2^-160, the last term is truncated by three limbs
+        let epsilon = ldexp(1.0, -80);
+        let x = Fixed::from_f64(1.0, 3).add_f64(epsilon);
+        let square = &x * &x;
+        assert_eq!(square.fraction_limbs(), 3);
+        assert_eq!((&square - &Fixed::from_f64(1.0, 3)).to_f64(), 2.0 * epsilon);
+    }
+
+    #[test]
+    fn with_fraction_limbs_truncates() {
+        let value = Fixed::from_f64(1.0, 3).add_f64(ldexp(1.0, -80)).add_f64(0.5);
+        assert_eq!(value.with_fraction_limbs(1).to_f64(), 1.5);
+        assert_eq!(value.with_fraction_limbs(1).with_fraction_limbs(3).to_f64(), 1.5);
+    }
+
+    #[test]
+    fn frexp_splits_into_mantissa_and_exponent() {
+        assert_eq!(frexp(8.0), (0.5, 4));
+        assert_eq!(frexp(-0.75), (-0.75, 0));
+        assert_eq!(frexp(1.0), (0.5, 1));
+        assert_eq!(frexp(0.0), (0.0, 0));
+        for value in [3.0, -1e-300, 1e300, 0.1, ldexp(1.0, -1060), f64::MAX] {
+            let (mantissa, exponent) = frexp(value);
+            assert!((0.5..1.0).contains(&mantissa.abs()), "{}", value);
+            assert_eq!(ldexp(mantissa, exponent), value);
+        }
+    }
+
+    #[test]
+    fn ldexp_reaches_beyond_a_single_power() {
+        assert_eq!(ldexp(1.5, 3), 12.0);
+        assert_eq!(ldexp(1.0, -1074), 5e-324);
+        assert_eq!(ldexp(1.0, 1024), f64::INFINITY);
+    }
+
+    #[test]
+    fn decimals_round_trip() {
+        for decimal in ["0", "0.5", "-3.25", "4294967295", "0.0000152587890625"] {
+            assert_eq!(decimal.parse::<Fixed>().unwrap().to_string(), decimal);
+        }
+        // Other decimals are truncated to the limbs, printing them gives the value back once
+        // truncated to the same limbs
+        let round_trip = |value: &Fixed| value.to_string().parse::<Fixed>().unwrap().with_fraction_limbs(value.fraction_limbs());
+        let value: Fixed = "-0.7436438870371587047521915061".parse().unwrap();
+        assert_eq!(value.fraction_limbs(), 3);
+        assert_eq!(value.to_string(), "-0.7436438870371587047521915061");
+        assert_eq!(round_trip(&value), value);
+        let tiny: Fixed = "0.000000000000000000000000000001".parse().unwrap();
+        assert_eq!(round_trip(&tiny), tiny);
+        assert!((tiny.to_f64() - 1e-30).abs() < ldexp(1.0, -128));
+        let thirds = Fixed { negative: false, limbs: vec![0x5555_5555; 5] };
+        assert_eq!(round_trip(&thirds), thirds);
+        let largest = Fixed { negative: true, limbs: vec![u32::MAX; 3] };
+        assert_eq!(largest.to_string(), "-4294967295.99999999999999999995");
+        assert_eq!(round_trip(&largest), largest);
+
+        assert_eq!("+.5".parse::<Fixed>().unwrap(), Fixed::from_f64(0.5, 1));
+        assert_eq!("-0".parse::<Fixed>().unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn invalid_decimals_are_rejected() {
+        for decimal in ["", "-", ".", "1.2.3", "1e5", "0x10", "- 1", "4294967296"] {
+            assert_eq!(decimal.parse::<Fixed>(), Err(ParseFixedError), "{:?}", decimal);
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Center {
+        x: Fixed,
+    }
+
+    #[test]
+    fn serde_round_trips() {
+        let center = Center { x: "-0.7436438870371587047521915061".parse().unwrap() };
+        let text = toml::to_string(&center).unwrap();
+        assert_eq!(text.trim(), r#"x = "-0.7436438870371587047521915061""#);
+        assert_eq!(toml::from_str::<Center>(&text).unwrap(), center);
+    }
+
+    #[test]
+    fn serde_reads_numbers() {
+        assert_eq!(toml::from_str::<Center>("x = -0.25").unwrap().x, fixed(-0.25));
+        assert_eq!(toml::from_str::<Center>("x = 3").unwrap().x, fixed(3.0));
+        assert!(toml::from_str::<Center>("x = 1e20").is_err());
+        assert!(toml::from_str::<Center>("x = nan").is_err());
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::HashSet;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferUsage, CopyImageToBufferInfo, PrimaryAutoCommandBuffer, RenderPassBeginInfo, SubpassContents};
+use vulkano::device::{Device, DeviceExtensions, Queue};
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::format::ClearColorValue;
+use vulkano::image::{ImageAccess, ImageUsage, StorageImage};
+use vulkano::image::view::ImageView;
+use vulkano::instance::Instance;
+use vulkano::instance::debug::DebugUtilsMessenger;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::render_pass::{Framebuffer, RenderPass};
+use vulkano::swapchain::{self, AcquireError, Surface, Swapchain, SwapchainCreateInfo, SwapchainCreationError, SwapchainPresentInfo};
+use vulkano::sync::future::FenceSignalFuture;
+use vulkano::sync::{self, FlushError, GpuFuture};
+use winit::window::Window;
+
+use crate::compute_rays_pipeline::ComputeRaysPipeline;
+use crate::draw_pipeline::DrawPipeline;
+use crate::error::Error;
+use crate::params::Params;
+use crate::pass::{Frame, FrameUniforms, Pass, PassStage, Scene};
+use crate::screenshot::{self, Capture, CaptureSource};
+use crate::vulkan::{self, DeviceSelector, LayerConfig};
+
+/// The Vulkan objects shared by everything that renders: instance, device, queue and allocators.
+pub struct Context {
+    pub instance: Arc<Instance>,
+    pub physical_device: Arc<PhysicalDevice>,
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+    pub memory_allocator: Arc<StandardMemoryAllocator>,
+    pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    /// Logs validation messages while it is alive, `None` without `ext_debug_utils`
+    pub debug_messenger: Option<DebugUtilsMessenger>,
+}
+
+impl Context {
+    /// Picks a device that can present to `surface`, `instance` must have been created windowed.
+    /// `device` overrides the automatic choice.
+    pub fn windowed(
+        instance: Arc<Instance>,
+        surface: &Arc<Surface>,
+        device: Option<&DeviceSelector>,
+    ) -> Result<Context, Error> {
+        let (physical_device, queue_family_index) =
+            vulkan::create_physical_device(instance.clone(), surface.clone(), device)?;
+        Context::new(instance, physical_device, queue_family_index, vulkan::device_extensions())
+    }
+
+    /// Picks a device with a compute capable queue, no window system needed.
+    pub fn headless(layer_config: &LayerConfig, device: Option<&DeviceSelector>) -> Result<Context, Error> {
+        let instance = vulkan::create_instance(false, layer_config)?;
+        let (physical_device, queue_family_index) =
+            vulkan::create_headless_physical_device(instance.clone(), device)?;
+        Context::new(instance, physical_device, queue_family_index, DeviceExtensions::empty())
+    }
+
+    fn new(
+        instance: Arc<Instance>,
+        physical_device: Arc<PhysicalDevice>,
+        queue_family_index: u32,
+        enabled_extensions: DeviceExtensions,
+    ) -> Result<Context, Error> {
+        let debug_messenger = vulkan::create_debug_messenger(instance.clone())?;
+        let properties = physical_device.properties();
+        tracing::info!("Using {} ({:?})", properties.device_name, properties.device_type);
+
+        let (device, mut queues) = vulkan::create_device(physical_device.clone(), queue_family_index, enabled_extensions)?;
+        // One queue was requested
+        let queue = queues.next().unwrap();
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+        let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+            device.clone(),
+            StandardCommandBufferAllocatorCreateInfo::default(),
+        ));
+
+        Ok(Context {
+            instance,
+            physical_device,
+            device,
+            queue,
+            memory_allocator,
+            command_buffer_allocator,
+            debug_messenger,
+        })
+    }
+}
+
+/// Colour of the parts of the image no fractal is drawn to
+pub const DEFAULT_CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+/// Signalled once a submitted frame has been presented
+type FrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture>>>;
+
+/// A screenshot to copy in the next frame
+struct CaptureRequest {
+    source: CaptureSource,
+    path: PathBuf,
+    params: Params,
+}
+
+/// Renders a `Scene` into a window: owns the swapchain, the storage images the passes share and
+/// the command buffers, which are prebuilt per swapchain image and only rebuilt when invalidated.
+/// Frames in between differ only by their frame index, which lets the path tracer refine its
+/// average until the scene changes.
+pub struct Renderer {
+    context: Context,
+    window: Arc<Window>,
+    swapchain: Arc<Swapchain>,
+    render_pass: Arc<RenderPass>,
+    framebuffers: Vec<Arc<Framebuffer>>,
+    image_view: Arc<ImageView<StorageImage>>,
+    accumulation_view: Arc<ImageView<StorageImage>>,
+    /// Per-frame uniforms of each swapchain image's command buffer
+    frame_uniforms: Vec<Subbuffer<FrameUniforms>>,
+    /// Frames submitted since the command buffers were rebuilt
+    frame_index: u32,
+    /// Written to the uniforms of every frame, see `set_palette_shift`
+    palette_shift: f32,
+    passes: Vec<Box<dyn Pass>>,
+    clear_color: [f32; 4],
+    command_buffers: Vec<Arc<PrimaryAutoCommandBuffer>>,
+    fences: Vec<Option<FrameFence>>,
+    previous_fence_i: u32,
+    window_resized: bool,
+    recreate_swapchain: bool,
+    scene_changed: bool,
+    capture_requests: Vec<CaptureRequest>,
+    /// Copies recorded into frames that may still be running
+    captures_in_flight: Vec<(FrameFence, Capture)>,
+    /// Threads converting and writing finished captures
+    saving: Vec<JoinHandle<()>>,
+    /// Paths handed out for screenshots, whose files may not have been written yet
+    screenshot_paths: HashSet<PathBuf>,
+}
+
+impl Renderer {
+    /// Starts with the fractal compute pass followed by the pass drawing its image to the window.
+    /// `surface` must have been created from a winit `Window`.
+    pub fn new(context: Context, surface: Arc<Surface>) -> Result<Renderer, Error> {
+        let window = surface
+            .object()
+            .ok_or(Error::NotAWindow)?
+            .clone()
+            .downcast::<Window>()
+            .map_err(|_| Error::NotAWindow)?;
+
+        let (swapchain, images) = vulkan::create_swapchain(
+            context.physical_device.clone(),
+            context.device.clone(),
+            window.clone(),
+            surface,
+        )?;
+        let render_pass = vulkan::get_render_pass(context.device.clone(), &swapchain)?;
+        let framebuffers = vulkan::get_framebuffers(&images, &render_pass)?;
+
+        // Images - follow the window extent
+        let (_, image_view) = vulkan::get_image(
+            &context.memory_allocator,
+            context.queue.clone(),
+            window.inner_size().into(),
+        )?;
+        let (_, accumulation_view) = vulkan::get_accumulation_image(
+            &context.memory_allocator,
+            context.queue.clone(),
+            window.inner_size().into(),
+        )?;
+        let frame_uniforms = images
+            .iter()
+            .map(|_| FrameUniforms::default().upload(&context.memory_allocator))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let compute_pipeline = ComputeRaysPipeline::new(
+            context.queue.clone(),
+            context.command_buffer_allocator.clone(),
+            context.memory_allocator.clone(),
+        )?;
+        let draw_pipeline = DrawPipeline::new(
+            context.queue.clone(),
+            render_pass.clone(),
+            context.command_buffer_allocator.clone(),
+        )?;
+
+        Ok(Renderer {
+            fences: vec![None; images.len()],
+            context,
+            window,
+            swapchain,
+            render_pass,
+            framebuffers,
+            image_view,
+            accumulation_view,
+            frame_uniforms,
+            frame_index: 0,
+            palette_shift: 0.0,
+            passes: vec![Box::new(compute_pipeline), Box::new(draw_pipeline)],
+            clear_color: DEFAULT_CLEAR_COLOR,
+            command_buffers: Vec::new(),
+            previous_fence_i: 0,
+            window_resized: false,
+            recreate_swapchain: false,
+            scene_changed: true,
+            capture_requests: Vec::new(),
+            captures_in_flight: Vec::new(),
+            saving: Vec::new(),
+            screenshot_paths: HashSet::new(),
+        })
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    pub fn window(&self) -> &Arc<Window> {
+        &self.window
+    }
+
+    /// Size of the storage image, the extent a `Scene` should fill.
+    pub fn image_extent(&self) -> [u32; 2] {
+        self.image_view.image().dimensions().width_height()
+    }
+
+    /// Render pass graphics passes have to be created for.
+    pub fn render_pass(&self) -> &Arc<RenderPass> {
+        &self.render_pass
+    }
+
+    /// Adds a pass after the existing ones of its stage.
+    pub fn add_pass(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+        self.scene_changed = true;
+    }
+
+    /// Colour of the parts of the window no fractal is drawn to.
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+        self.scene_changed = true;
+    }
+
+    /// A path for a screenshot taken at `time`, see `screenshot::file_name`. Paths of existing
+    /// files and of screenshots still being saved are not handed out again.
+    pub fn screenshot_path(&mut self, template: &str, params: &Params, source: CaptureSource, time: SystemTime) -> PathBuf {
+        let path = screenshot::file_name(template, params, source, time, |path| {
+            self.screenshot_paths.contains(path) || path.exists()
+        });
+        self.screenshot_paths.insert(path.clone());
+        path
+    }
+
+    /// Copies `source` at the next frame and saves it to `path` in the background, PNGs with
+    /// `params` embedded.
+    pub fn capture(&mut self, source: CaptureSource, path: PathBuf, params: Params) {
+        let format = match source {
+            CaptureSource::Image => self.image_view.image().format(),
+            CaptureSource::Window => {
+                if !self.swapchain.image_usage().intersects(ImageUsage::TRANSFER_SRC) {
+                    tracing::error!("The surface does not allow copying from the window");
+                    return;
+                }
+                self.swapchain.image_format()
+            }
+        };
+        if !screenshot::is_supported(format) {
+            tracing::error!("{}", Error::CaptureFormat(format));
+            return;
+        }
+
+        self.capture_requests.push(CaptureRequest { source, path, params });
+    }
+
+    /// The swapchain and storage image are recreated at the next frame.
+    pub fn window_resized(&mut self) {
+        self.window_resized = true;
+    }
+
+    /// The command buffers are rebuilt from the scene at the next frame.
+    pub fn scene_changed(&mut self) {
+        self.scene_changed = true;
+    }
+
+    /// Shifts the palette of the fractal regions by `shift` palette lengths from the next frame
+    /// on, without rebuilding the command buffers.
+    pub fn set_palette_shift(&mut self, shift: f32) {
+        self.palette_shift = shift;
+    }
+
+    // The fences are only shared within the renderer, vulkano implements `GpuFuture` for `Arc` only
+    /// Presents the next frame. `scene` is only asked for when the command buffers are rebuilt,
+    /// with the extent of the storage image.
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn render(&mut self, scene: impl FnOnce([u32; 2]) -> Result<Scene, Error>) -> Result<(), Error> {
+        self.save_finished_captures();
+
+        if self.window_resized || self.recreate_swapchain {
+            self.recreate_swapchain = false;
+
+            let new_dimensions = self.window.inner_size();
+
+            // A minimized window has no extent, wait until it is restored
+            if new_dimensions.width == 0 || new_dimensions.height == 0 {
+                return Ok(());
+            }
+
+            let (new_swapchain, new_images) = match self.swapchain.recreate(SwapchainCreateInfo {
+                image_extent: new_dimensions.into(), // here, "image_extend" will correspond to the window dimensions
+                ..self.swapchain.create_info()
+            }) {
+                Ok(r) => r,
+                // This error tends to happen when the user is manually resizing the window.
+                // Simply restarting the loop is the easiest way to fix this issue.
+                Err(SwapchainCreationError::ImageExtentNotSupported { .. }) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            self.swapchain = new_swapchain;
+            self.framebuffers = vulkan::get_framebuffers(&new_images, &self.render_pass)?;
+
+            if self.window_resized {
+                self.window_resized = false;
+
+                // In flight frames keep the old images alive through their command buffers
+                (_, self.image_view) = vulkan::get_image(
+                    &self.context.memory_allocator,
+                    self.context.queue.clone(),
+                    new_dimensions.into(),
+                )?;
+                (_, self.accumulation_view) = vulkan::get_accumulation_image(
+                    &self.context.memory_allocator,
+                    self.context.queue.clone(),
+                    new_dimensions.into(),
+                )?;
+            }
+
+            // The command buffers reference the framebuffers and image, so always rebuild them
+            self.scene_changed = true;
+        }
+
+        if self.scene_changed {
+            self.scene_changed = false;
+            self.command_buffers = self.build_command_buffers(&scene(self.image_extent())?)?;
+            // The path tracer starts a new average
+            self.frame_index = 0;
+        }
+
+        let (image_i, suboptimal, acquire_future) =
+            match swapchain::acquire_next_image(self.swapchain.clone(), None) {
+                Ok(r) => r,
+                Err(AcquireError::OutOfDate) => {
+                    self.recreate_swapchain = true;
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+        if suboptimal {
+            self.recreate_swapchain = true;
+        }
+
+        // Wait for the fence related to this image to finish (normally this would be the oldest fence)
+        if let Some(image_fence) = &self.fences[image_i as usize] {
+            image_fence.wait(None)?;
+        }
+
+        // The frame that last used the uniforms of this image has finished
+        *self.frame_uniforms[image_i as usize].write().map_err(Error::BufferAccess)? = FrameUniforms {
+            frame_index: self.frame_index,
+            palette_shift: self.palette_shift,
+        };
+        self.frame_index = self.frame_index.saturating_add(1);
+
+        let previous_future = match self.fences[self.previous_fence_i as usize].clone() {
+            None => {
+                let mut now = sync::now(self.context.device.clone());
+                now.cleanup_finished();
+
+                now.boxed()
+            }
+            Some(fence) => fence.boxed()
+        };
+
+        let (capture_commands, captures) = self.record_captures(image_i as usize)?;
+
+        let mut future = previous_future
+            .join(acquire_future)
+            .then_execute(self.context.queue.clone(), self.command_buffers[image_i as usize].clone())?
+            .boxed();
+        if let Some(commands) = capture_commands {
+            future = future.then_execute(self.context.queue.clone(), commands)?.boxed();
+        }
+
+        let future = future
+            .then_swapchain_present(
+                self.context.queue.clone(),
+                SwapchainPresentInfo::swapchain_image_index(self.swapchain.clone(), image_i),
+            )
+            .boxed()
+            .then_signal_fence_and_flush();
+
+        self.fences[image_i as usize] = match future {
+            Ok(value) => Some(Arc::new(value)),
+            Err(FlushError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                None
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // Without a fence the frame was not submitted, the requests are copied in the next one
+        if let Some(fence) = &self.fences[image_i as usize] {
+            self.capture_requests.clear();
+            self.captures_in_flight.extend(captures.into_iter().map(|capture| (fence.clone(), capture)));
+        }
+
+        self.previous_fence_i = image_i;
+        Ok(())
+    }
+
+    /// Copies the requested screenshots of the frame drawing into swapchain image `image_i`.
+    fn record_captures(&self, image_i: usize) -> Result<(Option<PrimaryAutoCommandBuffer>, Vec<Capture>), Error> {
+        if self.capture_requests.is_empty() {
+            return Ok((None, Vec::new()));
+        }
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.context.command_buffer_allocator.as_ref(),
+            self.context.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        let mut captures = Vec::new();
+        for request in &self.capture_requests {
+            let image = match request.source {
+                CaptureSource::Image => self.image_view.image().clone() as Arc<dyn ImageAccess>,
+                CaptureSource::Window => self.framebuffers[image_i].attachments()[0].image(),
+            };
+
+            let format = image.format();
+            let extent = image.dimensions().width_height();
+            let buffer = Buffer::new_slice::<u8>(
+                &self.context.memory_allocator,
+                BufferCreateInfo {
+                    usage: BufferUsage::TRANSFER_DST,
+                    ..Default::default()
+                },
+                AllocationCreateInfo {
+                    usage: MemoryUsage::Download,
+                    ..Default::default()
+                },
+                extent[0] as u64 * extent[1] as u64 * 4,
+            )?;
+            builder.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, buffer.clone()))?;
+
+            captures.push(Capture {
+                buffer,
+                format,
+                extent,
+                path: request.path.clone(),
+                params: request.params.clone(),
+            });
+        }
+
+        Ok((Some(builder.build()?), captures))
+    }
+
+    /// Hands the captures of finished frames to saving threads.
+    fn save_finished_captures(&mut self) {
+        let (finished, in_flight) = mem::take(&mut self.captures_in_flight)
+            .into_iter()
+            .partition(|(fence, _)| fence.is_signaled().unwrap_or(false));
+        self.captures_in_flight = in_flight;
+
+        self.saving.retain(|thread| !thread.is_finished());
+        for (_, capture) in finished {
+            self.saving.push(capture.save_in_background());
+        }
+    }
+
+    fn build_command_buffers(&self, scene: &Scene) -> Result<Vec<Arc<PrimaryAutoCommandBuffer>>, Error> {
+        self.framebuffers
+            .iter()
+            .enumerate()
+            .map(|(i, framebuffer)| {
+                let extent = framebuffer.extent();
+                let viewport = Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [extent[0] as f32, extent[1] as f32],
+                    depth_range: 0.0..1.0,
+                };
+                let frame = Frame {
+                    image_view: &self.image_view,
+                    accumulation_view: &self.accumulation_view,
+                    uniforms: &self.frame_uniforms[i],
+                    viewport: &viewport,
+                    scene,
+                };
+
+                let mut builder = AutoCommandBufferBuilder::primary(
+                    self.context.command_buffer_allocator.as_ref(),
+                    self.context.queue.queue_family_index(),
+                    CommandBufferUsage::MultipleSubmit, // don't forget to write the correct buffer usage
+                )?;
+
+                builder.clear_color_image(ClearColorImageInfo {
+                    clear_value: ClearColorValue::Float(self.clear_color),
+                    ..ClearColorImageInfo::image(self.image_view.image().clone())
+                })?;
+
+                // Execute the compute passes, the command buffer builder inserts the barrier
+                // between the storage image write and the fragment shader read before the renderpass
+                for pass in self.passes.iter().filter(|pass| pass.stage() == PassStage::Compute) {
+                    for commands in pass.record(&frame)? {
+                        builder.execute_commands(commands)?;
+                    }
+                }
+
+                // Start a renderpass for the framebuffer
+                builder.begin_render_pass(
+                    RenderPassBeginInfo {
+                        clear_values: vec![Some(self.clear_color.into())],
+                        ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+                    },
+                    SubpassContents::SecondaryCommandBuffers,
+                )?;
+
+                for pass in self.passes.iter().filter(|pass| pass.stage() == PassStage::Graphics) {
+                    for commands in pass.record(&frame)? {
+                        builder.execute_commands(commands)?;
+                    }
+                }
+
+                // End renderpass
+                builder.end_render_pass()?;
+
+                let command_buffer = builder.build()?;
+                vulkan::set_object_name(&command_buffer, &format!("frame {}", i));
+                Ok(Arc::new(command_buffer))
+            })
+            .collect()
+    }
+}
+
+impl Drop for Renderer {
+    /// Finishes the screenshots that are still being copied or saved.
+    fn drop(&mut self) {
+        for (fence, capture) in mem::take(&mut self.captures_in_flight) {
+            if fence.wait(None).is_ok() {
+                self.saving.push(capture.save_in_background());
+            }
+        }
+        for thread in self.saving.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::RgbaImage;
+use vulkano::buffer::Subbuffer;
+use vulkano::format::Format;
+
+use crate::error::Error;
+use crate::params::{self, Params};
+
+/// File name template used when none is given
+pub const DEFAULT_TEMPLATE: &str = "sel-{time}.png";
+
+/// Which image a screenshot copies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureSource {
+    /// The storage image the compute passes render into
+    Image,
+    /// The swapchain image as presented, including what the graphics passes draw over it
+    Window,
+}
+
+impl CaptureSource {
+    fn name(self) -> &'static str {
+        match self {
+            CaptureSource::Image => "image",
+            CaptureSource::Window => "window",
+        }
+    }
+}
+
+/// An image copied into a host visible buffer, to be saved once the copy has finished.
+pub struct Capture {
+    pub buffer: Subbuffer<[u8]>,
+    pub format: Format,
+    pub extent: [u32; 2],
+    pub path: PathBuf,
+    pub params: Params,
+}
+
+impl Capture {
+    /// Converts and writes the image on another thread, the GPU must be done with the buffer.
+    pub fn save_in_background(self) -> JoinHandle<()> {
+        thread::spawn(move || match self.save() {
+            Ok(()) => tracing::info!("Saved screenshot to {}", self.path.display()),
+            Err(e) => tracing::error!("{}", e),
+        })
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let content = self.buffer.read().map_err(Error::BufferAccess)?;
+        let mut pixels = content.to_vec();
+        match self.format {
+            Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => {}
+            Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => {
+                for pixel in pixels.chunks_exact_mut(4) {
+                    pixel.swap(0, 2);
+                }
+            }
+            format => return Err(Error::CaptureFormat(format)),
+        }
+
+        // sRGB formats hold encoded values already, the bytes are what the display shows
+        let image = RgbaImage::from_raw(self.extent[0], self.extent[1], pixels).ok_or(Error::Readback(self.extent))?;
+        params::save_image(&self.path, &image, &self.params)
+    }
+}
+
+/// Whether `Capture` can convert images of `format`.
+pub fn is_supported(format: Format) -> bool {
+    matches!(
+        format,
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB | Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB
+    )
+}
+
+/// Expands the placeholders of a file name template: `{time}` (UTC, `YYYYMMDD-hhmmss`),
+/// `{source}`, `{kind}`, `{zoom}`, `{iterations}`, `{width}` and `{height}`. A counter is added
+/// before the extension if `is_taken` says the name is in use already.
+pub fn file_name(
+    template: &str,
+    params: &Params,
+    source: CaptureSource,
+    time: SystemTime,
+    is_taken: impl Fn(&Path) -> bool,
+) -> PathBuf {
+    let path = PathBuf::from(expand_template(template, params, source, time));
+    if !is_taken(&path) {
+        return path;
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let parent = path.parent().unwrap_or(Path::new("")).to_owned();
+    (2..)
+        .map(|i| parent.join(format!("{}-{}{}", stem, i, extension)))
+        .find(|path| !is_taken(path))
+        .unwrap()
+}
+
+fn expand_template(template: &str, params: &Params, source: CaptureSource, time: SystemTime) -> String {
+    let kind = format!("{:?}", params.fractal.kind).to_lowercase();
+    template
+        .replace("{time}", &utc_timestamp(time))
+        .replace("{source}", source.name())
+        .replace("{kind}", &kind)
+        .replace("{zoom}", &format!("{:.3e}", params.view.zoom))
+        .replace("{iterations}", &params.fractal.max_iterations.to_string())
+        .replace("{width}", &params.resolution[0].to_string())
+        .replace("{height}", &params.resolution[1].to_string())
+}
+
+/// `YYYYMMDD-hhmmss` in UTC, sorts chronologically.
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, seconds_of_day) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(utc_timestamp(at(0)), "19700101-000000");
+        assert_eq!(utc_timestamp(at(951827696)), "20000229-123456");
+        // Leap days and the month boundaries around them
+        assert_eq!(utc_timestamp(at(1709164800 - 1)), "20240228-235959");
+        assert_eq!(utc_timestamp(at(1709164800)), "20240229-000000");
+        assert_eq!(utc_timestamp(at(1709164800 + 86400)), "20240301-000000");
+        assert_eq!(utc_timestamp(at(1677628800 - 1)), "20230228-235959");
+        assert_eq!(utc_timestamp(at(1677628800)), "20230301-000000");
+        // 2100 is not a leap year
+        assert_eq!(utc_timestamp(at(4107542400 - 1)), "21000228-235959");
+        assert_eq!(utc_timestamp(at(4107542400)), "21000301-000000");
+        // Year boundary
+        assert_eq!(utc_timestamp(at(1640995200 - 1)), "20211231-235959");
+        assert_eq!(utc_timestamp(at(1640995200)), "20220101-000000");
+        // Before the epoch
+        assert_eq!(utc_timestamp(UNIX_EPOCH - Duration::from_secs(1)), "19700101-000000");
+    }
+
+    #[test]
+    fn templates_expand_every_placeholder() {
+        let mut params = Params::default();
+        params.view.zoom = 1500.0;
+        params.fractal.max_iterations = 500;
+        params.resolution = [640, 480];
+        let name = |template: &str, source| expand_template(template, &params, source, at(951827696));
+
+        assert_eq!(name("{time}", CaptureSource::Image), "20000229-123456");
+        assert_eq!(name("{source}", CaptureSource::Image), "image");
+        assert_eq!(name("{source}", CaptureSource::Window), "window");
+        assert_eq!(name("{kind}", CaptureSource::Image), "mandelbrot");
+        assert_eq!(name("{zoom}", CaptureSource::Image), "1.500e3");
+        assert_eq!(name("{iterations}", CaptureSource::Image), "500");
+        assert_eq!(name("{width}x{height}", CaptureSource::Image), "640x480");
+        assert_eq!(name(DEFAULT_TEMPLATE, CaptureSource::Image), "sel-20000229-123456.png");
+        assert_eq!(name("{unknown}-{source}.png", CaptureSource::Image), "{unknown}-image.png");
+    }
+
+    #[test]
+    fn taken_names_get_a_counter() {
+        let params = Params::default();
+        let mut taken = HashSet::new();
+        for expected in ["shots/sel-19700101-000000.png", "shots/sel-19700101-000000-2.png", "shots/sel-19700101-000000-3.png"] {
+            let path = file_name("shots/sel-{time}.png", &params, CaptureSource::Image, at(0), |path| {
+                taken.contains(path)
+            });
+            assert_eq!(path, PathBuf::from(expected));
+            taken.insert(path);
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use serde::{Deserialize, Serialize};
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
+
+use crate::error::Error;
+use crate::mesh::MeshInstance;
+
+/// `vec4`s per instruction of an encoded scene, `INSTRUCTION_SIZE` in the shaders
+pub const INSTRUCTION_SIZE: usize = 4;
+/// Distances the shader holds at once while evaluating a scene, `STACK_SIZE` in the shader
+pub const STACK_SIZE: usize = 16;
+/// Repetitions, twists and bends the shader can nest, `POINT_STACK_SIZE` in the shader
+pub const POINT_STACK_SIZE: usize = 8;
+
+// Instructions after the shapes, the `OP_*` defines in the shader
+const OP_UNION: u32 = 16;
+const OP_INTERSECTION: u32 = 17;
+const OP_SUBTRACTION: u32 = 18;
+const OP_REPEAT: u32 = 19;
+const OP_TWIST: u32 = 20;
+const OP_BEND: u32 = 21;
+const OP_END: u32 = 22;
+
+/// Primitive with a signed distance function.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "shape", rename_all = "lowercase")]
+pub enum Shape {
+    Sphere { center: [f32; 3], radius: f32 },
+    /// Axis aligned, extending `half_size` from the center in each direction
+    Box { center: [f32; 3], half_size: [f32; 3] },
+    /// The points at `height` along the unit `normal`, the side the normal points to is outside
+    Plane { normal: [f32; 3], height: f32 },
+    /// Ring around the y axis
+    Torus { center: [f32; 3], major_radius: f32, minor_radius: f32 },
+    /// Line segment from `start` to `end` with a radius
+    Capsule { start: [f32; 3], end: [f32; 3], radius: f32 },
+}
+
+impl Shape {
+    /// Matches the `SHAPE_*` defines in the ray marching shader.
+    fn id(&self) -> u32 {
+        match self {
+            Shape::Sphere { .. } => 0,
+            Shape::Box { .. } => 1,
+            Shape::Plane { .. } => 2,
+            Shape::Torus { .. } => 3,
+            Shape::Capsule { .. } => 4,
+        }
+    }
+
+    /// Two points or vectors and a scalar, as the shader reads them.
+    fn operands(&self) -> ([f32; 3], [f32; 3], f32) {
+        match *self {
+            Shape::Sphere { center, radius } => (center, [0.0; 3], radius),
+            Shape::Box { center, half_size } => (center, half_size, 0.0),
+            Shape::Plane { normal, height } => (normal, [0.0; 3], height),
+            Shape::Torus { center, major_radius, minor_radius } => (center, [major_radius, 0.0, 0.0], minor_radius),
+            Shape::Capsule { start, end, radius } => (start, end, radius),
+        }
+    }
+}
+
+/// How light scatters off a surface in the path tracer, the ray marcher shades everything as
+/// diffuse. Written as an inline table, e.g. `material = { type = "metal", roughness = 0.2 }`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Material {
+    /// Scatters in all directions, like chalk
+    #[default]
+    Diffuse,
+    /// Reflects, blurred by a `roughness` from 0 for a mirror to 1
+    Metal {
+        #[serde(default)]
+        roughness: f32,
+    },
+    /// Clear like glass or water, refracting by the index of refraction `ior`, tinted by the colour
+    Dielectric {
+        #[serde(default = "default_ior")]
+        ior: f32,
+    },
+    /// Glows in its colour times `strength`, lighting the scene
+    Emissive {
+        #[serde(default = "default_strength")]
+        strength: f32,
+    },
+}
+
+fn default_ior() -> f32 {
+    1.5
+}
+
+fn default_strength() -> f32 {
+    1.0
+}
+
+impl Material {
+    /// Matches the `MATERIAL_*` defines in the path tracing shader, with the parameter.
+    pub fn encode(&self) -> [f32; 2] {
+        match *self {
+            Material::Diffuse => [0.0, 0.0],
+            Material::Metal { roughness } => [1.0, roughness],
+            Material::Dielectric { ior } => [2.0, ior],
+            Material::Emissive { strength } => [3.0, strength],
+        }
+    }
+
+    fn is_diffuse(&self) -> bool {
+        *self == Material::Diffuse
+    }
+}
+
+/// A shape with a surface colour and material.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Object {
+    #[serde(flatten)]
+    pub shape: Shape,
+    #[serde(default = "default_color")]
+    pub color: [f32; 3],
+    #[serde(default, skip_serializing_if = "Material::is_diffuse")]
+    pub material: Material,
+}
+
+fn default_color() -> [f32; 3] {
+    [0.8, 0.8, 0.8]
+}
+
+/// Combines or deforms its children. `blend` is the distance over which the seams between
+/// children are rounded off, colours blend along with the surfaces and materials switch halfway.
+/// 0 keeps the seams sharp.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    /// Inside any child
+    Union {
+        #[serde(default)]
+        blend: f32,
+        children: Vec<Node>,
+    },
+    /// Inside every child
+    Intersection {
+        #[serde(default)]
+        blend: f32,
+        children: Vec<Node>,
+    },
+    /// Inside the first child and outside the others, in the colour of the first
+    Subtraction {
+        #[serde(default)]
+        blend: f32,
+        children: Vec<Node>,
+    },
+    /// The children repeated every `period` along each axis, 0 does not repeat along that axis.
+    /// The children should fit into one period around the origin.
+    Repeat { period: [f32; 3], children: Vec<Node> },
+    /// The children twisted around the y axis by `rate` radians per unit of height
+    Twist { rate: f32, children: Vec<Node> },
+    /// The children bent in the xy plane by `rate` radians per unit along x
+    Bend { rate: f32, children: Vec<Node> },
+}
+
+impl Operation {
+    fn children(&self) -> &[Node] {
+        match self {
+            Operation::Union { children, .. }
+            | Operation::Intersection { children, .. }
+            | Operation::Subtraction { children, .. }
+            | Operation::Repeat { children, .. }
+            | Operation::Twist { children, .. }
+            | Operation::Bend { children, .. } => children,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Union { .. } => "union",
+            Operation::Intersection { .. } => "intersection",
+            Operation::Subtraction { .. } => "subtraction",
+            Operation::Repeat { .. } => "repeat",
+            Operation::Twist { .. } => "twist",
+            Operation::Bend { .. } => "bend",
+        }
+    }
+
+    /// Deformations change the point their children are evaluated at.
+    fn is_deformation(&self) -> bool {
+        matches!(self, Operation::Repeat { .. } | Operation::Twist { .. } | Operation::Bend { .. })
+    }
+}
+
+/// Node of a scene tree, a table with a `shape` key is an object and one with an `op` key an
+/// operation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, try_from = "toml::Table")]
+pub enum Node {
+    Object(Object),
+    Operation(Operation),
+}
+
+impl TryFrom<toml::Table> for Node {
+    type Error = String;
+
+    // Picking the variant by key keeps the error of that variant, an untagged enum only
+    // reports that none matched
+    fn try_from(table: toml::Table) -> Result<Node, String> {
+        let is_operation = table.contains_key("op");
+        let value = toml::Value::Table(table);
+        let node = if is_operation {
+            value.try_into().map(Node::Operation)
+        } else {
+            value.try_into().map(Node::Object)
+        };
+        node.map_err(|e| e.to_string())
+    }
+}
+
+impl Node {
+    fn validate(&self) -> Result<(), Error> {
+        if let Node::Operation(operation) = self {
+            if operation.children().is_empty() {
+                return Err(Error::Scene(format!("a {} has no children", operation.name())));
+            }
+            for child in operation.children() {
+                child.validate()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Distances and points the shader has to hold at once while evaluating the node.
+    fn stack_depths(&self) -> (usize, usize) {
+        match self {
+            Node::Object(_) => (1, 0),
+            Node::Operation(operation) => {
+                let (distances, points) = stack_depths(operation.children());
+                (distances, points + operation.is_deformation() as usize)
+            }
+        }
+    }
+
+    fn encode(&self, program: &mut Vec<[f32; 4]>) {
+        let operation = match self {
+            Node::Object(object) => {
+                let (a, b, scalar) = object.shape.operands();
+                let color = object.color;
+                let [material, parameter] = object.material.encode();
+                program.extend([
+                    [a[0], a[1], a[2], object.shape.id() as f32],
+                    [b[0], b[1], b[2], scalar],
+                    [color[0], color[1], color[2], 0.0],
+                    [material, parameter, 0.0, 0.0],
+                ]);
+                return;
+            }
+            Node::Operation(operation) => operation,
+        };
+
+        let (op, operand) = match *operation {
+            Operation::Union { blend, ref children } => return encode_combined(children, OP_UNION, blend, program),
+            Operation::Intersection { blend, ref children } => {
+                return encode_combined(children, OP_INTERSECTION, blend, program);
+            }
+            Operation::Subtraction { blend, ref children } => {
+                return encode_combined(children, OP_SUBTRACTION, blend, program);
+            }
+            Operation::Repeat { period, .. } => (OP_REPEAT, period),
+            Operation::Twist { rate, .. } => (OP_TWIST, [rate, 0.0, 0.0]),
+            Operation::Bend { rate, .. } => (OP_BEND, [rate, 0.0, 0.0]),
+        };
+        push_instruction(program, op, operand);
+        encode_combined(operation.children(), OP_UNION, 0.0, program);
+        push_instruction(program, OP_END, [0.0; 3]);
+    }
+}
+
+/// Depths of evaluating `nodes` in turn, each combined with the result of the ones before.
+fn stack_depths(nodes: &[Node]) -> (usize, usize) {
+    nodes.iter().enumerate().fold((0, 0), |(distances, points), (i, node)| {
+        let (node_distances, node_points) = node.stack_depths();
+        (distances.max(node_distances + (i > 0) as usize), points.max(node_points))
+    })
+}
+
+/// Evaluates `nodes` in turn and combines each with the result of the ones before by `op`.
+fn encode_combined(nodes: &[Node], op: u32, blend: f32, program: &mut Vec<[f32; 4]>) {
+    for (i, node) in nodes.iter().enumerate() {
+        node.encode(program);
+        if i > 0 {
+            push_instruction(program, op, [blend, 0.0, 0.0]);
+        }
+    }
+}
+
+fn push_instruction(program: &mut Vec<[f32; 4]>, op: u32, operand: [f32; 3]) {
+    program.extend([[operand[0], operand[1], operand[2], op as f32], [0.0; 4], [0.0; 4], [0.0; 4]]);
+}
+
+/// Objects and lighting of a ray marched scene, colours are in linear light.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SdfScene {
+    /// Direction towards the sun, does not need to be normalized
+    pub light_direction: [f32; 3],
+    /// Colour of rays that hit nothing, also tints the ambient light
+    pub sky_color: [f32; 3],
+    /// Steps along a ray before giving up on hitting anything
+    pub max_steps: u32,
+    /// Distance from the camera beyond which rays hit nothing
+    pub max_distance: f32,
+    /// Share of the distance to the scene a ray advances per step. Twists and bends make the
+    /// distances overestimate, values below 1 keep rays from passing through their surfaces.
+    pub step_scale: f32,
+    /// Soft shadows cast by the sun
+    pub shadows: bool,
+    /// Darkens creases and corners the ambient light hardly reaches
+    pub ambient_occlusion: bool,
+    /// Objects and operations, combined by a union
+    pub objects: Vec<Node>,
+    /// Triangle meshes loaded from files, rendered along with the objects
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub meshes: Vec<MeshInstance>,
+}
+
+impl Default for SdfScene {
+    /// One of each shape on a floor.
+    fn default() -> Self {
+        let object = |shape, color| Node::Object(Object { shape, color, material: Material::Diffuse });
+        SdfScene {
+            light_direction: [-0.5, 0.8, -0.3],
+            sky_color: [0.55, 0.7, 0.9],
+            max_steps: 256,
+            max_distance: 100.0,
+            step_scale: 1.0,
+            shadows: true,
+            ambient_occlusion: true,
+            objects: vec![
+                object(Shape::Plane { normal: [0.0, 1.0, 0.0], height: 0.0 }, [0.6, 0.6, 0.55]),
+                object(Shape::Sphere { center: [0.0, 1.0, 0.0], radius: 1.0 }, [0.9, 0.25, 0.2]),
+                object(Shape::Box { center: [-2.5, 0.75, 0.5], half_size: [0.75; 3] }, [0.25, 0.5, 0.9]),
+                object(
+                    Shape::Torus { center: [2.5, 0.3, 0.5], major_radius: 0.8, minor_radius: 0.3 },
+                    [0.95, 0.75, 0.2],
+                ),
+                object(
+                    Shape::Capsule { start: [-1.0, 0.4, 2.5], end: [1.0, 1.6, 3.0], radius: 0.4 },
+                    [0.3, 0.8, 0.4],
+                ),
+            ],
+            meshes: Vec::new(),
+        }
+    }
+}
+
+impl SdfScene {
+    /// Checks that every operation has children and that the tree fits into the stacks of the
+    /// shader.
+    pub fn validate(&self) -> Result<(), Error> {
+        for node in &self.objects {
+            node.validate()?;
+        }
+
+        let (distances, points) = stack_depths(&self.objects);
+        if distances > STACK_SIZE {
+            return Err(Error::Scene(format!(
+                "the scene needs {} distances at once, the shader holds {}, try moving larger subtrees first",
+                distances, STACK_SIZE,
+            )));
+        }
+        if points > POINT_STACK_SIZE {
+            return Err(Error::Scene(format!(
+                "repetitions, twists and bends are nested {} deep, the shader allows {}",
+                points, POINT_STACK_SIZE,
+            )));
+        }
+        Ok(())
+    }
+
+    /// The scene tree as a postfix program for the shaders, `INSTRUCTION_SIZE` `vec4`s per
+    /// instruction with the instruction in the `w` of the first. Objects push their distance,
+    /// with the first operand and the shape, then the second operand and the scalar, the colour
+    /// and the material with its parameter.
+    /// Combining instructions replace the top two distances with one, with the blend in `x`.
+    /// Deformations transform the point until the matching end instruction.
+    pub fn encode(&self) -> Vec<[f32; 4]> {
+        let mut program = Vec::new();
+        encode_combined(&self.objects, OP_UNION, 0.0, &mut program);
+        program
+    }
+
+    /// Storage buffer with the encoded program. Buffers cannot be empty, a scene without objects
+    /// holds a single unused entry.
+    pub fn upload(&self, memory_allocator: &StandardMemoryAllocator) -> Result<Subbuffer<[[f32; 4]]>, Error> {
+        let mut data = self.encode();
+        if data.is_empty() {
+            data.push([0.0; 4]);
+        }
+
+        Ok(Buffer::from_iter(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                usage: MemoryUsage::Upload,
+                ..Default::default()
+            },
+            data,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere() -> Node {
+        Node::Object(Object {
+            shape: Shape::Sphere { center: [0.0; 3], radius: 1.0 },
+            color: [1.0, 0.5, 0.25],
+            material: Material::Metal { roughness: 0.2 },
+        })
+    }
+
+    fn cube() -> Node {
+        Node::Object(Object {
+            shape: Shape::Box { center: [1.0, 2.0, 3.0], half_size: [0.5; 3] },
+            color: default_color(),
+            material: Material::Diffuse,
+        })
+    }
+
+    fn union(children: Vec<Node>) -> Node {
+        Node::Operation(Operation::Union { blend: 0.0, children })
+    }
+
+    fn twist(children: Vec<Node>) -> Node {
+        Node::Operation(Operation::Twist { rate: 0.5, children })
+    }
+
+    fn scene(objects: Vec<Node>) -> SdfScene {
+        SdfScene { objects, ..SdfScene::default() }
+    }
+
+    /// The opcode or shape of each instruction.
+    fn opcodes(program: &[[f32; 4]]) -> Vec<u32> {
+        program.chunks_exact(INSTRUCTION_SIZE).map(|instruction| instruction[0][3] as u32).collect()
+    }
+
+    #[test]
+    fn nested_operations_need_deeper_stacks() {
+        assert_eq!(stack_depths(&[sphere()]), (1, 0));
+        assert_eq!(stack_depths(&[sphere(), sphere(), sphere()]), (2, 0));
+        // The left child is done before the right one starts
+        assert_eq!(stack_depths(&[union(vec![union(vec![sphere(), sphere()]), sphere()])]), (2, 0));
+        assert_eq!(stack_depths(&[union(vec![sphere(), union(vec![sphere(), sphere()])])]), (3, 0));
+        assert_eq!(stack_depths(&[sphere(), union(vec![sphere(), sphere()])]), (3, 0));
+        assert_eq!(stack_depths(&[twist(vec![twist(vec![sphere()])]), twist(vec![sphere()])]), (2, 2));
+    }
+
+    #[test]
+    fn scenes_beyond_the_stacks_are_rejected() {
+        // Every union nested into the right child holds one more distance
+        let right_nested = |depth: usize| (1..depth).fold(sphere(), |node, _| union(vec![sphere(), node]));
+        assert!(scene(vec![right_nested(STACK_SIZE)]).validate().is_ok());
+        assert!(matches!(scene(vec![right_nested(STACK_SIZE + 1)]).validate(), Err(Error::Scene(_))));
+
+        let twisted = |depth: usize| (0..depth).fold(sphere(), |node, _| twist(vec![node]));
+        assert!(scene(vec![twisted(POINT_STACK_SIZE)]).validate().is_ok());
+        assert!(matches!(scene(vec![twisted(POINT_STACK_SIZE + 1)]).validate(), Err(Error::Scene(_))));
+
+        assert!(matches!(scene(vec![union(vec![sphere(), union(Vec::new())])]).validate(), Err(Error::Scene(_))));
+        assert!(SdfScene::default().validate().is_ok());
+    }
+
+    #[test]
+    fn objects_encode_into_one_instruction() {
+        let program = scene(vec![sphere()]).encode();
+        assert_eq!(program, vec![
+            [0.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+            [1.0, 0.5, 0.25, 0.0],
+            [1.0, 0.2, 0.0, 0.0],
+        ]);
+    }
+
+    #[test]
+    fn operations_encode_in_postfix_order() {
+        let subtraction = Node::Operation(Operation::Subtraction { blend: 0.5, children: vec![cube(), sphere()] });
+        let program = scene(vec![sphere(), subtraction, twist(vec![cube(), sphere()])]).encode();
+        assert_eq!(program.len(), 11 * INSTRUCTION_SIZE);
+        // The twisted children are combined before the end of the twist, the result with the rest after it
+        assert_eq!(opcodes(&program), [
+            0,
+            1,
+            0,
+            OP_SUBTRACTION,
+            OP_UNION,
+            OP_TWIST,
+            1,
+            0,
+            OP_UNION,
+            OP_END,
+            OP_UNION,
+        ]);
+        // Combining instructions carry the blend, deformations their parameter
+        assert_eq!(program[3 * INSTRUCTION_SIZE], [0.5, 0.0, 0.0, OP_SUBTRACTION as f32]);
+        assert_eq!(program[5 * INSTRUCTION_SIZE], [0.5, 0.0, 0.0, OP_TWIST as f32]);
+        assert!(program[5 * INSTRUCTION_SIZE + 1..6 * INSTRUCTION_SIZE].iter().all(|v| *v == [0.0; 4]));
+    }
+}
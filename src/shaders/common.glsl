@@ -0,0 +1,76 @@
+// Interface shared by the compute kernels, they all render into the same image with the same
+// push constants so one `PushConstants` struct serves all of them.
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(set = 0, binding = 0, rgba8) uniform writeonly image2D img;
+
+layout(set = 0, binding = 1) readonly buffer Palette {
+    vec4 colors[];
+} palette;
+
+// Written before every submission, see `FrameUniforms`
+layout(set = 0, binding = 3) uniform FrameUniforms {
+    uint frame_index;
+    // Added to the palette offset, cycles the palette without recording the commands again
+    float palette_shift;
+} frame;
+
+layout(push_constant) uniform PushConstants {
+    // The center split into a high and low part, their sum carries double precision
+    vec2 center_hi;
+    vec2 center_lo;
+    float zoom;
+    float rotation;
+    uint max_iterations;
+    float escape_radius;
+    float palette_offset;
+    float palette_period;
+    vec2 julia_c;
+    // Part of the image to render into, the view is fitted to this region
+    ivec2 region_offset;
+    ivec2 region_size;
+    uint kind;
+    // 1 / zoom as mantissa * 2^exponent, for zooms beyond the range of a float
+    float scale_mantissa;
+    int scale_exponent;
+    // Points in the reference orbit of the perturbation kernel
+    uint orbit_length;
+    // Colour of the points that never escape, alpha is unused
+    vec4 interior_color;
+} params;
+
+#define KIND_MANDELBROT 0
+#define KIND_JULIA 1
+
+// Linear interpolated palette lookup, t wraps around
+vec3 palette_color(float t) {
+    uint size = palette.colors.length();
+    float x = fract(t) * float(size);
+    uint i = uint(x) % size;
+    return mix(palette.colors[i].rgb, palette.colors[(i + 1) % size].rgb, fract(x));
+}
+
+// Position of the pixel in the region, rotated and centered, before dividing by the zoom.
+// The region height spans 2 units, see `View::pixel_offset`
+vec2 pixel_direction(ivec2 pixel) {
+    vec2 size = vec2(params.region_size);
+    vec2 uv = (vec2(pixel) + vec2(0.5) - 0.5 * size) / (0.5 * size.y);
+    float s = sin(params.rotation);
+    float co = cos(params.rotation);
+    return mat2(co, s, -s, co) * uv;
+}
+
+// Colors the pixel by its iteration count `i` and the final value `z`
+void store_color(ivec2 pixel, uint i, vec2 z) {
+    // Points that never escape are part of the set
+    vec3 color = params.interior_color.rgb;
+    if (i < params.max_iterations) {
+        // Normalized iteration count, continuous across iteration bands
+        float smooth_i = float(i) + 1.0 - log2(log(length(z)) / log(params.escape_radius));
+        color = palette_color(smooth_i / params.palette_period + params.palette_offset + frame.palette_shift);
+    }
+
+    vec4 to_write = vec4(color, 1.0);
+    imageStore(img, params.region_offset + pixel, to_write);
+}
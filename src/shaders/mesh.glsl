@@ -0,0 +1,222 @@
+// Triangle meshes shared by the signed distance kernels, laid out by `Mesh::upload`. Rays find
+// the closest triangle through a bounding volume hierarchy. The including kernel declares
+// `params` with `triangle_count`.
+
+struct MeshVertex {
+    vec3 position;
+    float u;
+    // Zero for flat shaded triangles
+    vec3 normal;
+    float v;
+};
+
+struct BvhNode {
+    vec3 lower;
+    // First child of inner nodes, whose second child follows it, or first triangle of leaves
+    uint first;
+    vec3 upper;
+    // Triangles of leaves, 0 for inner nodes
+    uint count;
+};
+
+struct MeshMaterial {
+    vec3 color;
+    // As written by `Material::encode`
+    float kind;
+    float parameter;
+    // Base colour texture in `texels`, a width of 0 has none
+    uint first_texel;
+    uint width;
+    uint height;
+};
+
+layout(set = 0, binding = 5) readonly buffer Vertices {
+    MeshVertex data[];
+} vertices;
+
+// Vertex indices and material of each triangle
+layout(set = 0, binding = 6) readonly buffer Triangles {
+    uvec4 data[];
+} triangles;
+
+// The root first
+layout(set = 0, binding = 7) readonly buffer Nodes {
+    BvhNode data[];
+} nodes;
+
+layout(set = 0, binding = 8) readonly buffer MeshMaterials {
+    MeshMaterial data[];
+} mesh_materials;
+
+// sRGB texels of all textures, rows from the top
+layout(set = 0, binding = 9) readonly buffer Texels {
+    uint data[];
+} texels;
+
+// One more than `MAX_DEPTH` in mesh.rs, the depth `Mesh::build_hierarchy` caps the leaves at
+#define NODE_STACK_SIZE 32
+
+// Distance of boxes a ray misses
+const float NO_HIT = 1e30;
+
+// Triangles closer to the origin of a ray than this are not hit, so rays leaving a surface do
+// not hit it right away
+const float MIN_HIT_DISTANCE = 1e-5;
+
+struct MeshHit {
+    // Distance along the ray
+    float t;
+    uint triangle;
+    // Weights of the second and third vertex
+    vec2 barycentric;
+};
+
+// Distance along the ray to where it enters the box, or `NO_HIT` if it misses the box before
+// `t_max`
+float box_distance(BvhNode node, vec3 origin, vec3 inverse_direction, float t_max) {
+    vec3 t0 = (node.lower - origin) * inverse_direction;
+    vec3 t1 = (node.upper - origin) * inverse_direction;
+    vec3 entries = min(t0, t1);
+    vec3 exits = max(t0, t1);
+    float entry = max(max(entries.x, entries.y), max(entries.z, 0.0));
+    float exit = min(min(exits.x, exits.y), min(exits.z, t_max));
+    return entry <= exit ? entry : NO_HIT;
+}
+
+// Möller-Trumbore intersection from either side, replaces `hit` if the triangle is closer
+bool intersect_triangle(vec3 origin, vec3 direction, uint triangle, inout MeshHit hit) {
+    uvec4 indices = triangles.data[triangle];
+    vec3 a = vertices.data[indices.x].position;
+    vec3 ab = vertices.data[indices.y].position - a;
+    vec3 ac = vertices.data[indices.z].position - a;
+
+    vec3 p = cross(direction, ac);
+    float determinant = dot(ab, p);
+    // Parallel to the triangle
+    if (determinant == 0.0) {
+        return false;
+    }
+    float inverse = 1.0 / determinant;
+    vec3 s = origin - a;
+    float u = dot(s, p) * inverse;
+    if (u < 0.0 || u > 1.0) {
+        return false;
+    }
+    vec3 q = cross(s, ab);
+    float v = dot(direction, q) * inverse;
+    if (v < 0.0 || u + v > 1.0) {
+        return false;
+    }
+    float t = dot(ac, q) * inverse;
+    if (t < MIN_HIT_DISTANCE || t >= hit.t) {
+        return false;
+    }
+    hit = MeshHit(t, triangle, vec2(u, v));
+    return true;
+}
+
+// Closest triangle the ray crosses before `t_max`, `hit.t` stays `t_max` if there is none
+bool intersect_meshes(vec3 origin, vec3 direction, float t_max, out MeshHit hit) {
+    hit = MeshHit(t_max, 0, vec2(0.0));
+    if (params.triangle_count == 0) {
+        return false;
+    }
+
+    // Axes the ray is parallel to get a large finite factor, infinity times 0 would be NaN
+    vec3 inverse_direction = 1.0 / mix(direction, vec3(1e-20), equal(direction, vec3(0.0)));
+    uint stack[NODE_STACK_SIZE];
+    float stack_distances[NODE_STACK_SIZE];
+    uint top = 0;
+    bool found = false;
+
+    float root_distance = box_distance(nodes.data[0], origin, inverse_direction, hit.t);
+    if (root_distance == NO_HIT) {
+        return false;
+    }
+    stack[top] = 0;
+    stack_distances[top++] = root_distance;
+
+    while (top > 0) {
+        top--;
+        // Boxes further than a triangle found after pushing them cannot hold a closer one
+        if (stack_distances[top] > hit.t) {
+            continue;
+        }
+        BvhNode node = nodes.data[stack[top]];
+
+        if (node.count > 0) {
+            for (uint i = node.first; i < node.first + node.count; i++) {
+                found = intersect_triangle(origin, direction, i, hit) || found;
+            }
+            continue;
+        }
+
+        // The nearer child is pushed last to be visited first
+        float left = box_distance(nodes.data[node.first], origin, inverse_direction, hit.t);
+        float right = box_distance(nodes.data[node.first + 1], origin, inverse_direction, hit.t);
+        bool left_first = left <= right;
+        float nearer = min(left, right);
+        float further = max(left, right);
+        if (further < NO_HIT) {
+            stack[top] = left_first ? node.first + 1 : node.first;
+            stack_distances[top++] = further;
+        }
+        if (nearer < NO_HIT) {
+            stack[top] = left_first ? node.first : node.first + 1;
+            stack_distances[top++] = nearer;
+        }
+    }
+    return found;
+}
+
+// Texel at `position`, at most one texel outside the texture, wrapping around its edges, in
+// linear light
+vec3 texel(MeshMaterial material, ivec2 position) {
+    ivec2 size = ivec2(material.width, material.height);
+    // The remainder of negative numbers is undefined
+    ivec2 wrapped = (position + size) % size;
+    vec3 srgb = unpackUnorm4x8(texels.data[material.first_texel + uint(wrapped.y * size.x + wrapped.x)]).rgb;
+    return pow(srgb, vec3(2.2));
+}
+
+// Bilinear sample of the base colour texture
+vec3 sample_texture(MeshMaterial material, vec2 uv) {
+    vec2 position = fract(uv) * vec2(material.width, material.height) - 0.5;
+    ivec2 corner = ivec2(floor(position));
+    vec2 f = fract(position);
+    return mix(
+        mix(texel(material, corner), texel(material, corner + ivec2(1, 0)), f.x),
+        mix(texel(material, corner + ivec2(0, 1)), texel(material, corner + ivec2(1, 1)), f.x),
+        f.y
+    );
+}
+
+// Colour and material at a hit, with the distance along the ray
+Surface mesh_surface(MeshHit hit) {
+    uvec4 indices = triangles.data[hit.triangle];
+    MeshMaterial material = mesh_materials.data[indices.w];
+    vec3 color = material.color;
+    if (material.width > 0) {
+        vec3 weights = vec3(1.0 - hit.barycentric.x - hit.barycentric.y, hit.barycentric);
+        MeshVertex a = vertices.data[indices.x];
+        MeshVertex b = vertices.data[indices.y];
+        MeshVertex c = vertices.data[indices.z];
+        vec2 uv = weights.x * vec2(a.u, a.v) + weights.y * vec2(b.u, b.v) + weights.z * vec2(c.u, c.v);
+        color *= sample_texture(material, uv);
+    }
+    return Surface(color, hit.t, vec2(material.kind, material.parameter));
+}
+
+// Interpolated vertex normal at a hit, or the normal of the triangle if it is flat shaded
+vec3 mesh_normal(MeshHit hit) {
+    uvec4 indices = triangles.data[hit.triangle];
+    MeshVertex a = vertices.data[indices.x];
+    MeshVertex b = vertices.data[indices.y];
+    MeshVertex c = vertices.data[indices.z];
+    vec3 weights = vec3(1.0 - hit.barycentric.x - hit.barycentric.y, hit.barycentric);
+    vec3 n = weights.x * a.normal + weights.y * b.normal + weights.z * c.normal;
+    if (dot(n, n) > 1e-12) {
+        return normalize(n);
+    }
+    return normalize(cross(b.position - a.position, c.position - a.position));
+}
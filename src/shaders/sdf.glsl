@@ -0,0 +1,149 @@
+// Evaluation of the signed distance scenes encoded by `SdfScene::encode`, shared by the ray
+// marching and path tracing kernels. The including kernel declares `params` with the
+// `max_distance` and `instruction_count` of the scene.
+
+// `INSTRUCTION_SIZE` vec4s per instruction, see `SdfScene::encode`
+layout(set = 0, binding = 1) readonly buffer Program {
+    vec4 data[];
+} program;
+
+#define INSTRUCTION_SIZE 4
+
+#define SHAPE_SPHERE 0
+#define SHAPE_BOX 1
+#define SHAPE_PLANE 2
+#define SHAPE_TORUS 3
+#define SHAPE_CAPSULE 4
+
+// Below the operations, anything else is a shape
+#define OP_UNION 16
+#define OP_INTERSECTION 17
+#define OP_SUBTRACTION 18
+#define OP_REPEAT 19
+#define OP_TWIST 20
+#define OP_BEND 21
+#define OP_END 22
+
+// Must match `sdf::STACK_SIZE` and `sdf::POINT_STACK_SIZE`, scenes are validated against them
+#define STACK_SIZE 16
+#define POINT_STACK_SIZE 8
+
+// Offset of the samples of the distance gradient
+const float NORMAL_EPSILON = 1e-3;
+
+// Closest surface of a scene, the material is its kind and parameter as written by
+// `Material::encode`
+struct Surface {
+    vec3 color;
+    float distance;
+    vec2 material;
+};
+
+float shape_distance(int shape, vec4 a, vec4 b, vec3 p) {
+    switch (shape) {
+        case SHAPE_SPHERE:
+            return length(p - a.xyz) - b.w;
+        case SHAPE_BOX: {
+            vec3 q = abs(p - a.xyz) - b.xyz;
+            return length(max(q, 0.0)) + min(max(q.x, max(q.y, q.z)), 0.0);
+        }
+        case SHAPE_PLANE:
+            return dot(p, a.xyz) - b.w;
+        case SHAPE_TORUS: {
+            vec3 q = p - a.xyz;
+            return length(vec2(length(q.xz) - b.x, q.y)) - b.w;
+        }
+        case SHAPE_CAPSULE: {
+            vec3 pa = p - a.xyz;
+            vec3 ba = b.xyz - a.xyz;
+            float h = clamp(dot(pa, ba) / max(dot(ba, ba), 1e-12), 0.0, 1.0);
+            return length(pa - ba * h) - b.w;
+        }
+    }
+    return params.max_distance;
+}
+
+// Combines two colours and distances, rounding the seam off over `blend` units. Intersections
+// are unions of the insides flipped, subtractions intersections with the second inside flipped.
+Surface combine(int op, float blend, Surface lhs, Surface rhs) {
+    if (op == OP_SUBTRACTION) {
+        rhs.distance = -rhs.distance;
+    }
+    float flip = op == OP_UNION ? 1.0 : -1.0;
+    float d1 = flip * lhs.distance;
+    float d2 = flip * rhs.distance;
+
+    // Share of the left hand side, polynomial smooth minimum
+    float h = blend > 0.0 ? clamp(0.5 + 0.5 * (d2 - d1) / blend, 0.0, 1.0) : float(d1 < d2);
+    float d = mix(d2, d1, h) - blend * h * (1.0 - h);
+    if (op == OP_SUBTRACTION) {
+        return Surface(lhs.color, flip * d, lhs.material);
+    }
+    return Surface(mix(rhs.color, lhs.color, h), flip * d, h >= 0.5 ? lhs.material : rhs.material);
+}
+
+mat2 rotation(float angle) {
+    float c = cos(angle);
+    float s = sin(angle);
+    return mat2(c, s, -s, c);
+}
+
+Surface evaluate(vec3 p) {
+    Surface stack[STACK_SIZE];
+    vec3 points[POINT_STACK_SIZE];
+    uint top = 0;
+    uint point_top = 0;
+
+    for (uint i = 0; i < params.instruction_count; i++) {
+        uint base = INSTRUCTION_SIZE * i;
+        vec4 a = program.data[base];
+        int op = int(a.w);
+        if (op < OP_UNION) {
+            float d = shape_distance(op, a, program.data[base + 1], p);
+            stack[top++] = Surface(program.data[base + 2].rgb, d, program.data[base + 3].xy);
+            continue;
+        }
+
+        switch (op) {
+            case OP_UNION:
+            case OP_INTERSECTION:
+            case OP_SUBTRACTION:
+                top--;
+                stack[top - 1] = combine(op, a.x, stack[top - 1], stack[top]);
+                break;
+            case OP_REPEAT: {
+                points[point_top++] = p;
+                // A period of 0 leaves the axis alone
+                vec3 period = max(a.xyz, 1e-6);
+                p = mix(p, p - period * round(p / period), greaterThan(a.xyz, vec3(0.0)));
+                break;
+            }
+            case OP_TWIST:
+                points[point_top++] = p;
+                p.xz = rotation(a.x * p.y) * p.xz;
+                break;
+            case OP_BEND:
+                points[point_top++] = p;
+                p.xy = rotation(a.x * p.x) * p.xy;
+                break;
+            case OP_END:
+                p = points[--point_top];
+                break;
+        }
+    }
+    return top > 0 ? stack[0] : Surface(vec3(0.0), params.max_distance, vec2(0.0));
+}
+
+float scene_distance(vec3 p) {
+    return evaluate(p).distance;
+}
+
+// Gradient of the distance field by central differences
+vec3 normal(vec3 p) {
+    vec2 e = vec2(NORMAL_EPSILON, 0.0);
+    return normalize(vec3(
+        scene_distance(p + e.xyy) - scene_distance(p - e.xyy),
+        scene_distance(p + e.yxy) - scene_distance(p - e.yxy),
+        scene_distance(p + e.yyx) - scene_distance(p - e.yyx)
+    ));
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+use crate::precision::Fixed;
+
+/// Fraction limbs beyond those needed to resolve a pixel at the current zoom
+const GUARD_LIMBS: usize = 2;
+
+/// Maps image pixels onto the complex plane. The image height always spans `2 / zoom` units,
+/// the width follows the aspect ratio. Must stay in sync with the mapping in the compute shaders.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct View {
+    /// Kept in fixed-point with enough precision for the zoom, see `View::fraction_limbs`
+    pub center: [Fixed; 2],
+    pub zoom: f64,
+    /// Counterclockwise, in radians
+    pub rotation: f64,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        View::new([-0.5, 0.0], 0.8)
+    }
+}
+
+impl View {
+    pub fn new(center: [f64; 2], zoom: f64) -> View {
+        let fraction_limbs = View::fraction_limbs(zoom);
+        View {
+            center: center.map(|c| Fixed::from_f64(c, fraction_limbs)),
+            zoom,
+            rotation: 0.0,
+        }
+    }
+
+    /// Fraction limbs of the center needed to address single pixels at `zoom`.
+    pub fn fraction_limbs(zoom: f64) -> usize {
+        let bits = zoom.max(1.0).log2().ceil() as usize;
+        bits / 32 + 1 + GUARD_LIMBS
+    }
+
+    /// Offset from the center on the complex plane of the pixel at `pixel` in an image of `size`.
+    pub fn pixel_offset(&self, pixel: [f64; 2], size: [f64; 2]) -> [f64; 2] {
+        let half_height = 0.5 * size[1];
+        let uv = [
+            (pixel[0] - 0.5 * size[0]) / half_height,
+            (pixel[1] - half_height) / half_height,
+        ];
+
+        let (sin, cos) = self.rotation.sin_cos();
+        [
+            (cos * uv[0] - sin * uv[1]) / self.zoom,
+            (sin * uv[0] + cos * uv[1]) / self.zoom,
+        ]
+    }
+
+    /// Position on the complex plane of the pixel at `pixel` in an image of `size`, in double precision.
+    pub fn pixel_to_plane(&self, pixel: [f64; 2], size: [f64; 2]) -> [f64; 2] {
+        let offset = self.pixel_offset(pixel, size);
+        [
+            self.center[0].to_f64() + offset[0],
+            self.center[1].to_f64() + offset[1],
+        ]
+    }
+
+    /// Moves the view so the plane follows a cursor drag of `delta` pixels.
+    pub fn pan(&mut self, delta: [f64; 2], size: [f64; 2]) {
+        let from = self.pixel_offset([0.0, 0.0], size);
+        let to = self.pixel_offset(delta, size);
+        self.move_center([from[0] - to[0], from[1] - to[1]]);
+    }
+
+    /// Multiplies the zoom by `factor` while keeping the point under `pixel` in place.
+    pub fn zoom_at(&mut self, pixel: [f64; 2], size: [f64; 2], factor: f64) {
+        let before = self.pixel_offset(pixel, size);
+        self.zoom *= factor;
+        let after = self.pixel_offset(pixel, size);
+
+        let fraction_limbs = View::fraction_limbs(self.zoom);
+        self.center = [
+            self.center[0].with_fraction_limbs(fraction_limbs),
+            self.center[1].with_fraction_limbs(fraction_limbs),
+        ];
+        self.move_center([before[0] - after[0], before[1] - after[1]]);
+    }
+
+    /// The part of the view covering `extent` pixels at `offset` in an image of `size`, as a view
+    /// of its own. Rendering every tile into its own image reproduces the whole image.
+    pub fn tile(&self, size: [f64; 2], offset: [f64; 2], extent: [f64; 2]) -> View {
+        let tile_center = [offset[0] + 0.5 * extent[0], offset[1] + 0.5 * extent[1]];
+        let mut tile = View {
+            zoom: self.zoom * size[1] / extent[1],
+            ..self.clone()
+        };
+
+        let fraction_limbs = View::fraction_limbs(tile.zoom);
+        tile.center = [
+            self.center[0].with_fraction_limbs(fraction_limbs),
+            self.center[1].with_fraction_limbs(fraction_limbs),
+        ];
+        tile.move_center(self.pixel_offset(tile_center, size));
+        tile
+    }
+
+    pub fn rotate(&mut self, angle: f64) {
+        self.rotation = (self.rotation + angle) % std::f64::consts::TAU;
+    }
+
+    fn move_center(&mut self, delta: [f64; 2]) {
+        self.center = [
+            self.center[0].add_f64(delta[0]),
+            self.center[1].add_f64(delta[1]),
+        ];
+    }
+}
@@ -2,107 +2,427 @@
  * Graphics setup and rendering code
  */
 
-use vulkano::buffer::{BufferContents, Subbuffer};
-use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator};
-use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, PrimaryAutoCommandBuffer, RenderPassBeginInfo, SubpassContents};
-use vulkano::device::{Device, DeviceCreateInfo, Queue, QueueCreateInfo, QueueFlags, DeviceExtensions};
+use vulkano::device::{Device, DeviceCreateInfo, Queue, QueueCreateInfo, QueueFlags, DeviceExtensions, Features};
 use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
-use vulkano::image::{ImageUsage, SwapchainImage};
+use vulkano::format::Format;
+use vulkano::image::{ImageAccess, ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage, SwapchainImage};
 use vulkano::image::view::ImageView;
-use vulkano::instance::{Instance, InstanceCreateInfo};
-use vulkano::pipeline::{GraphicsPipeline};
-use vulkano::pipeline::graphics::vertex_input::Vertex;
+use vulkano::instance::{Instance, InstanceCreateInfo, InstanceExtensions};
+use vulkano::instance::debug::{DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger, DebugUtilsMessengerCreateInfo, Message};
+use vulkano::memory::allocator::StandardMemoryAllocator;
 use vulkano::swapchain::{Swapchain, SwapchainCreateInfo};
 use vulkano::swapchain::Surface;
-use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass};
-use vulkano::VulkanLibrary;
+use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass};
+use vulkano::{VulkanLibrary, VulkanObject};
+use vulkano::device::DeviceOwned;
 
 use winit::window::Window;
 
+use crate::error::Error;
+
+use std::convert::Infallible;
 use std::env;
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
-use vulkano::instance::debug::{DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger, DebugUtilsMessengerCreateInfo};
 
-use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
-use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
-use vulkano::shader::ShaderModule;
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Instance layers to enable. Layers that are not installed are skipped with a warning.
+#[derive(Clone, Debug, Default)]
+pub struct LayerConfig {
+    /// Enables the Khronos validation layer
+    pub validation: bool,
+    /// Further layers by name
+    pub extra_layers: Vec<String>,
+}
 
-pub(crate) fn create_instance() -> Arc<Instance> {
-    let library = VulkanLibrary::new().expect("No local Vulkan library found.");
+impl LayerConfig {
+    /// Validation is on in debug builds unless `SEL_VALIDATION` is `0` or `false`, and can be
+    /// turned on in release builds by setting it to anything else. `SEL_LAYERS` holds a comma
+    /// separated list of extra layers.
+    pub fn from_env() -> LayerConfig {
+        let validation = match env::var("SEL_VALIDATION") {
+            Ok(value) => !matches!(value.trim(), "0" | "false"),
+            Err(_) => cfg!(debug_assertions),
+        };
+        let extra_layers = env::var("SEL_LAYERS")
+            .map(|layers| {
+                layers
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|layer| !layer.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
 
-    println!("List of Vulkan debugging layers available to use:");
-    let layers = library.layer_properties().unwrap();
-    for l in layers {
-        println!("\t{}", l.name());
+        LayerConfig { validation, extra_layers }
     }
 
-    let layers = vec!["VK_LAYER_KHRONOS_validation".to_owned()];
+    /// The requested layers that are installed, warns about the others.
+    fn available_layers(&self, library: &VulkanLibrary) -> Vec<String> {
+        let installed: Vec<String> = match library.layer_properties() {
+            Ok(layers) => layers.map(|l| l.name().to_owned()).collect(),
+            Err(e) => {
+                tracing::warn!("Could not list the Vulkan layers: {}", e);
+                Vec::new()
+            }
+        };
+        tracing::debug!("Installed Vulkan layers: {:?}", installed);
 
-    let mut required_extensions = vulkano_win::required_extensions(&library);
+        let requested = self.validation
+            .then(|| VALIDATION_LAYER.to_owned())
+            .into_iter()
+            .chain(self.extra_layers.iter().cloned());
 
-    let instance =
-        if env::consts::OS == "macos" {
-            // Enable the portability extension on macOS in order to support MoltenVK.
-            required_extensions.khr_portability_enumeration = true;
-            Instance::new(
-                library,
-                InstanceCreateInfo {
-                    enabled_extensions: required_extensions,
-                    enabled_layers: layers,
-                    enumerate_portability: true,
-                    ..Default::default()
-                },
-            ).expect("Failed to create a macos Vulkan instance.")
-        } else {
-            Instance::new(
-                library,
-                InstanceCreateInfo {
-                    enabled_layers: layers,
-                    enabled_extensions: required_extensions,
-                    enumerate_portability: false,
-                    ..Default::default()
-                },
-            ).expect("Failed to create Vulkan instance.")
+        let mut layers = Vec::new();
+        for layer in requested {
+            if layers.contains(&layer) {
+                continue;
+            }
+            if installed.contains(&layer) {
+                layers.push(layer);
+            } else if layer == VALIDATION_LAYER {
+                tracing::warn!("{} is not installed, continuing without validation. It comes with the Vulkan SDK.", layer);
+            } else {
+                tracing::warn!("Vulkan layer {} is not installed, skipping it", layer);
+            }
+        }
+        layers
+    }
+}
+
+/// Creates the Vulkan instance. Surface extensions are only requested when `windowed` is set, so
+/// a headless instance can be created on machines without a display server.
+pub fn create_instance(windowed: bool, layer_config: &LayerConfig) -> Result<Arc<Instance>, Error> {
+    let library = VulkanLibrary::new()?;
+
+    let layers = layer_config.available_layers(&library);
+    if !layers.is_empty() {
+        tracing::info!("Enabled Vulkan layers: {:?}", layers);
+    }
+
+    let mut required_extensions = if windowed {
+        vulkano_win::required_extensions(&library)
+    } else {
+        InstanceExtensions::empty()
+    };
+    // Validation messages and object names, see `create_debug_messenger`
+    required_extensions.ext_debug_utils = library.supported_extensions().ext_debug_utils;
+
+    let instance = if env::consts::OS == "macos" {
+        // Enable the portability extension on macOS in order to support MoltenVK.
+        required_extensions.khr_portability_enumeration = true;
+        Instance::new(
+            library,
+            InstanceCreateInfo {
+                enabled_extensions: required_extensions,
+                enabled_layers: layers,
+                enumerate_portability: true,
+                ..Default::default()
+            },
+        )?
+    } else {
+        Instance::new(
+            library,
+            InstanceCreateInfo {
+                enabled_layers: layers,
+                enabled_extensions: required_extensions,
+                enumerate_portability: false,
+                ..Default::default()
+            },
+        )?
+    };
+
+    Ok(instance)
+}
+
+/// Forwards debug-utils messages to tracing under the `vulkan` target, so they can be filtered
+/// like any other log. Returns `None` if the instance has no `ext_debug_utils`.
+pub fn create_debug_messenger(instance: Arc<Instance>) -> Result<Option<DebugUtilsMessenger>, Error> {
+    if !instance.enabled_extensions().ext_debug_utils {
+        return Ok(None);
+    }
+
+    let create_info = DebugUtilsMessengerCreateInfo {
+        // Filtering is left to the tracing subscriber
+        message_severity: DebugUtilsMessageSeverity::ERROR
+            | DebugUtilsMessageSeverity::WARNING
+            | DebugUtilsMessageSeverity::INFO
+            | DebugUtilsMessageSeverity::VERBOSE,
+        message_type: DebugUtilsMessageType::GENERAL
+            | DebugUtilsMessageType::VALIDATION
+            | DebugUtilsMessageType::PERFORMANCE,
+        ..DebugUtilsMessengerCreateInfo::user_callback(Arc::new(log_message))
+    };
+
+    // The callback only logs and never calls into Vulkan
+    let messenger = unsafe { DebugUtilsMessenger::new(instance, create_info)? };
+    Ok(Some(messenger))
+}
+
+fn log_message(message: &Message) {
+    let kind = if message.ty.intersects(DebugUtilsMessageType::VALIDATION) {
+        "validation"
+    } else if message.ty.intersects(DebugUtilsMessageType::PERFORMANCE) {
+        "performance"
+    } else {
+        "general"
+    };
+    let layer = message.layer_prefix.unwrap_or_default();
+    let id = message_id(message.description).unwrap_or_default();
+    let objects = object_names(message.description).join(", ");
+
+    macro_rules! log {
+        ($level:ident) => {
+            tracing::$level!(target: "vulkan", kind, layer, id, objects, "{}", message.description)
         };
+    }
+
+    if message.severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+        log!(error)
+    } else if message.severity.intersects(DebugUtilsMessageSeverity::WARNING) {
+        log!(warn)
+    } else if message.severity.intersects(DebugUtilsMessageSeverity::INFO) {
+        log!(debug)
+    } else {
+        log!(trace)
+    }
+}
 
-    return instance;
+/// The VUID or message name of a validation layer message, written as `[ VUID-... ]`.
+fn message_id(description: &str) -> Option<&str> {
+    let start = description.find("[ ")? + 2;
+    let end = start + description[start..].find(" ]")?;
+    Some(&description[start..end])
+}
+
+/// Names of the objects a validation layer message refers to, set with `set_object_name`.
+/// They are listed as `Object 0: handle = 0x..., name = ..., type = ...;`.
+fn object_names(description: &str) -> Vec<&str> {
+    description
+        .split("name = ")
+        .skip(1)
+        .filter_map(|rest| rest.find(", type = ").map(|end| &rest[..end]))
+        .collect()
+}
+
+/// Names `object` in validation messages and graphics debuggers. Does nothing if the instance
+/// has no `ext_debug_utils`.
+pub fn set_object_name<T: VulkanObject + DeviceOwned>(object: &T, name: &str) {
+    let device = object.device();
+    if !device.instance().enabled_extensions().ext_debug_utils {
+        return;
+    }
+    if let Err(e) = device.set_debug_utils_object_name(object, Some(name)) {
+        tracing::debug!("Could not name {}: {}", name, e);
+    }
 }
 
 pub fn device_extensions() -> DeviceExtensions {
-    return DeviceExtensions {
+    DeviceExtensions {
         khr_swapchain: true,
         ..DeviceExtensions::empty()
+    }
+}
+
+/// A device to use instead of the best ranked one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// Position in the list of `enumerate_devices`
+    Index(usize),
+    /// Case insensitive part of the device name
+    Name(String),
+}
+
+impl DeviceSelector {
+    /// Reads `SEL_DEVICE`, which takes an index or part of a name like `--device`.
+    pub fn from_env() -> Option<DeviceSelector> {
+        env::var("SEL_DEVICE")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| match value.parse() {
+                Ok(selector) => selector,
+                Err(never) => match never {},
+            })
+    }
+
+    fn matches(&self, index: usize, device: &PhysicalDevice) -> bool {
+        match self {
+            DeviceSelector::Index(i) => *i == index,
+            DeviceSelector::Name(name) => device
+                .properties()
+                .device_name
+                .to_lowercase()
+                .contains(&name.to_lowercase()),
+        }
+    }
+}
+
+impl FromStr for DeviceSelector {
+    type Err = Infallible;
+
+    /// Numbers select by index, anything else by name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(match s.parse() {
+            Ok(index) => DeviceSelector::Index(index),
+            Err(_) => DeviceSelector::Name(s.to_owned()),
+        })
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceSelector::Index(i) => write!(f, "index {}", i),
+            DeviceSelector::Name(name) => write!(f, "\"{}\"", name),
+        }
+    }
+}
+
+fn device_type_rank(device_type: PhysicalDeviceType) -> u32 {
+    match device_type {
+        PhysicalDeviceType::DiscreteGpu => 0,
+        PhysicalDeviceType::IntegratedGpu => 1,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 3,
+        _ => 4,
+    }
+}
+
+/// All devices in the order the driver reports them, the indices `DeviceSelector` refers to.
+pub fn enumerate_devices(instance: &Arc<Instance>) -> Result<Vec<Arc<PhysicalDevice>>, Error> {
+    let devices: Vec<_> = instance.enumerate_physical_devices()?.collect();
+    if devices.is_empty() {
+        return Err(Error::NoDevice("the Vulkan driver reports no devices".to_owned()));
+    }
+    Ok(devices)
+}
+
+/// The devices `selector` matches, or all of them without one.
+fn select_devices(instance: &Arc<Instance>, selector: Option<&DeviceSelector>) -> Result<Vec<Arc<PhysicalDevice>>, Error> {
+    let devices = enumerate_devices(instance)?;
+    let Some(selector) = selector else {
+        return Ok(devices);
     };
+
+    let selected: Vec<_> = devices
+        .into_iter()
+        .enumerate()
+        .filter(|(i, p)| selector.matches(*i, p))
+        .map(|(_, p)| p)
+        .collect();
+    if selected.is_empty() {
+        return Err(Error::NoDevice(format!("no device matches {}", selector)));
+    }
+    Ok(selected)
+}
+
+/// Properties, capabilities, memory heaps, queue families and extensions of a device, one per line.
+pub fn describe_device(physical_device: &PhysicalDevice) -> String {
+    let properties = physical_device.properties();
+    let mut lines = vec![
+        format!("{} ({:?})", properties.device_name, properties.device_type),
+        format!(
+            "  driver: {} {} (version {:#x})",
+            properties.driver_name.as_deref().unwrap_or("unknown"),
+            properties.driver_info.as_deref().unwrap_or_default(),
+            properties.driver_version,
+        ),
+        format!("  api version: {}", properties.api_version),
+        format!("  shader_float64: {}", physical_device.supported_features().shader_float64),
+        format!("  max image size: {}", properties.max_image_dimension2_d),
+        format!(
+            "  max compute work group: {:?}, {} invocations",
+            properties.max_compute_work_group_size,
+            properties.max_compute_work_group_invocations,
+        ),
+    ];
+
+    for (i, heap) in physical_device.memory_properties().memory_heaps.iter().enumerate() {
+        lines.push(format!("  memory heap {}: {} MiB {:?}", i, heap.size / (1024 * 1024), heap.flags));
+    }
+    for (i, family) in physical_device.queue_family_properties().iter().enumerate() {
+        lines.push(format!("  queue family {}: {} x {:?}", i, family.queue_count, family.queue_flags));
+    }
+
+    let extensions: Vec<_> = physical_device
+        .supported_extensions()
+        .into_iter()
+        .filter(|(_, supported)| *supported)
+        .map(|(name, _)| name)
+        .collect();
+    lines.push(format!("  extensions: {}", extensions.join(" ")));
+
+    lines.join("\n")
 }
 
-pub fn create_physical_device(instance: Arc<Instance>, surface: Arc<Surface>) -> (Arc<PhysicalDevice>, u32) {
-    let (physical_device, queue_family_index) = instance
-        .enumerate_physical_devices()
-        .expect("Could not enumerate devices.")
+/// Picks the best ranked device that can present to `surface`, among those `selector` matches.
+pub fn create_physical_device(
+    instance: Arc<Instance>,
+    surface: Arc<Surface>,
+    selector: Option<&DeviceSelector>,
+) -> Result<(Arc<PhysicalDevice>, u32), Error> {
+    let devices: Vec<_> = select_devices(&instance, selector)?
+        .into_iter()
         .filter(|p| p.supported_extensions().contains(&device_extensions()))
+        .collect();
+    if devices.is_empty() {
+        return Err(Error::NoDevice("no device supports khr_swapchain".to_owned()));
+    }
+
+    let mut candidates = Vec::new();
+    for p in devices {
+        for (i, q) in p.queue_family_properties().iter().enumerate() {
+            if q.queue_flags.contains(QueueFlags::GRAPHICS) && supports_surface(&p, i as u32, &surface) {
+                candidates.push((p.clone(), i as u32));
+                break;
+            }
+        }
+    }
+
+    candidates
+        .into_iter()
+        .min_by_key(|(p, _)| device_type_rank(p.properties().device_type))
+        .ok_or_else(|| Error::NoDevice("no device has a graphics queue that can present to the window".to_owned()))
+}
+
+/// Whether queue family `queue_family_index` of `p` can present to `surface`. A device whose
+/// support cannot be queried is passed over instead of failing the selection.
+fn supports_surface(p: &PhysicalDevice, queue_family_index: u32, surface: &Surface) -> bool {
+    p.surface_support(queue_family_index, surface).unwrap_or_else(|e| {
+        tracing::warn!("Could not query whether {} can present to the window: {}", p.properties().device_name, e);
+        false
+    })
+}
+
+/// Picks a device for offscreen rendering. No surface or swapchain support is required, so CPU
+/// implementations such as lavapipe are accepted as well.
+pub fn create_headless_physical_device(
+    instance: Arc<Instance>,
+    selector: Option<&DeviceSelector>,
+) -> Result<(Arc<PhysicalDevice>, u32), Error> {
+    select_devices(&instance, selector)?
+        .into_iter()
         .filter_map(|p| {
             p.queue_family_properties()
                 .iter()
-                .enumerate()
-                .position(|(i, q)| {
-                    q.queue_flags.contains(QueueFlags::GRAPHICS)
-                        && p.surface_support(i as u32, &surface).unwrap_or(false)
-                })
+                .position(|q| q.queue_flags.contains(QueueFlags::GRAPHICS | QueueFlags::COMPUTE))
                 .map(|q| (p, q as u32))
         })
-        .min_by_key(|(p, _)| match p.properties().device_type {
-            PhysicalDeviceType::DiscreteGpu => 0,
-            PhysicalDeviceType::IntegratedGpu => 1,
-            PhysicalDeviceType::VirtualGpu => 2,
-            PhysicalDeviceType::Cpu => 3,
-            _ => 4,
-        })
-        .expect("No device available.");
-
-    return (physical_device, queue_family_index);
+        .min_by_key(|(p, _)| device_type_rank(p.properties().device_type))
+        .ok_or_else(|| Error::NoDevice("no device has a queue supporting both graphics and compute".to_owned()))
 }
 
-pub fn create_device(physical_device: Arc<PhysicalDevice>, queue_family_index: u32) -> (Arc<Device>, impl ExactSizeIterator<Item=Arc<Queue>> + Sized) {
+/// Enables `shader_float64` when the device supports it, for the deep zoom kernel.
+pub fn create_device(physical_device: Arc<PhysicalDevice>, queue_family_index: u32, enabled_extensions: DeviceExtensions) -> Result<(Arc<Device>, impl ExactSizeIterator<Item=Arc<Queue>> + Sized), Error> {
+    let enabled_features = Features {
+        shader_float64: physical_device.supported_features().shader_float64,
+        ..Features::empty()
+    };
+
     let (device, queues) = Device::new(
         physical_device,
         DeviceCreateInfo {
@@ -110,47 +430,51 @@ pub fn create_device(physical_device: Arc<PhysicalDevice>, queue_family_index: u
                 queue_family_index,
                 ..Default::default()
             }],
-            enabled_extensions: device_extensions(),
+            enabled_extensions,
+            enabled_features,
             ..Default::default()
         },
-    )
-        .expect("Failed to create device.");
+    )?;
 
-    return (device, queues);
+    Ok((device, queues))
 }
 
-pub fn create_swapchain(physical_device: Arc<PhysicalDevice>, device: Arc<Device>, window: Arc<Window>, surface: Arc<Surface>) -> (Arc<Swapchain>, Vec<Arc<SwapchainImage>>) {
-    let caps = physical_device
-        .surface_capabilities(&surface, Default::default())
-        .expect("Failed to get surface capabilities.");
+pub fn create_swapchain(physical_device: Arc<PhysicalDevice>, device: Arc<Device>, window: Arc<Window>, surface: Arc<Surface>) -> Result<(Arc<Swapchain>, Vec<Arc<SwapchainImage>>), Error> {
+    let caps = physical_device.surface_capabilities(&surface, Default::default())?;
 
     let dimensions = window.inner_size();
-    let composite_alpha = caps.supported_composite_alpha.into_iter().next().unwrap();
-    let image_format = Some(
-        physical_device
-            .surface_formats(&surface, Default::default())
-            .unwrap()[0]
-            .0,
-    );
+    // Every conformant surface supports at least one composite alpha mode and format
+    let composite_alpha = caps
+        .supported_composite_alpha
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::NoDevice("the window surface supports no composite alpha mode".to_owned()))?;
+    let image_format = physical_device
+        .surface_formats(&surface, Default::default())?
+        .first()
+        .map(|(format, _)| *format)
+        .ok_or_else(|| Error::NoDevice("the window surface supports no image format".to_owned()))?;
 
     let (swapchain, images) = Swapchain::new(
         device.clone(),
         surface.clone(),
         SwapchainCreateInfo {
             min_image_count: caps.min_image_count,
-            image_format,
+            image_format: Some(image_format),
             image_extent: dimensions.into(),
-            image_usage: ImageUsage::COLOR_ATTACHMENT,
+            // Screenshots copy from the swapchain when the surface allows it
+            image_usage: ImageUsage::COLOR_ATTACHMENT
+                | (caps.supported_usage_flags & ImageUsage::TRANSFER_SRC),
             composite_alpha,
             ..Default::default()
         },
-    ).unwrap();
+    )?;
 
-    return (swapchain, images);
+    Ok((swapchain, images))
 }
 
-pub fn get_render_pass(device: Arc<Device>, swapchain: &Arc<Swapchain>) -> Arc<RenderPass> {
-    vulkano::single_pass_renderpass!(
+pub fn get_render_pass(device: Arc<Device>, swapchain: &Arc<Swapchain>) -> Result<Arc<RenderPass>, Error> {
+    let render_pass = vulkano::single_pass_renderpass!(
         device,
         attachments: {
             color: {
@@ -164,25 +488,74 @@ pub fn get_render_pass(device: Arc<Device>, swapchain: &Arc<Swapchain>) -> Arc<R
             color: [color],
             depth_stencil: {},
         },
-    ).unwrap()
+    )?;
+
+    Ok(render_pass)
 }
 
 pub fn get_framebuffers(
     images: &[Arc<SwapchainImage>],
     render_pass: &Arc<RenderPass>,
-) -> Vec<Arc<Framebuffer>> {
+) -> Result<Vec<Arc<Framebuffer>>, Error> {
     images
         .iter()
-        .map(|image| {
-            let view = ImageView::new_default(image.clone()).unwrap();
-            Framebuffer::new(
+        .enumerate()
+        .map(|(i, image)| {
+            set_object_name(&**image.inner().image, &format!("swapchain image {}", i));
+            let view = ImageView::new_default(image.clone())?;
+            let framebuffer = Framebuffer::new(
                 render_pass.clone(),
                 FramebufferCreateInfo {
                     attachments: vec![view],
                     ..Default::default()
                 },
-            )
-                .unwrap()
+            )?;
+            Ok(framebuffer)
         })
-        .collect::<Vec<_>>()
-}
\ No newline at end of file
+        .collect()
+}
+
+/// Storage image the compute passes render into and the draw pass reads from.
+pub fn get_image(memory_allocator: &StandardMemoryAllocator, queue: Arc<Queue>, dimensions: [u32; 2]) -> Result<(Arc<StorageImage>, Arc<ImageView<StorageImage>>), Error> {
+    let image = StorageImage::with_usage(
+        memory_allocator,
+        ImageDimensions::Dim2d {
+            width: dimensions[0],
+            height: dimensions[1],
+            array_layers: 1,
+        },
+        Format::R8G8B8A8_UNORM,
+        ImageUsage::TRANSFER_SRC
+            | ImageUsage::TRANSFER_DST
+            | ImageUsage::SAMPLED
+            | ImageUsage::STORAGE,
+        ImageCreateFlags::empty(),
+        Some(queue.queue_family_index()),
+    )?;
+    set_object_name(&**image.inner().image, "storage image");
+
+    let view = ImageView::new_default(image.clone())?;
+
+    Ok((image, view))
+}
+
+/// Storage image the path tracer averages its samples in, in linear light with float precision.
+pub fn get_accumulation_image(memory_allocator: &StandardMemoryAllocator, queue: Arc<Queue>, dimensions: [u32; 2]) -> Result<(Arc<StorageImage>, Arc<ImageView<StorageImage>>), Error> {
+    let image = StorageImage::with_usage(
+        memory_allocator,
+        ImageDimensions::Dim2d {
+            width: dimensions[0],
+            height: dimensions[1],
+            array_layers: 1,
+        },
+        Format::R32G32B32A32_SFLOAT,
+        ImageUsage::STORAGE,
+        ImageCreateFlags::empty(),
+        Some(queue.queue_family_index()),
+    )?;
+    set_object_name(&**image.inner().image, "accumulation image");
+
+    let view = ImageView::new_default(image.clone())?;
+
+    Ok((image, view))
+}